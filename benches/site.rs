@@ -0,0 +1,61 @@
+// Loading and querying a synthetic site of some thousands of pages and hundreds of groups.
+//
+// Run with `cargo bench`; the tree is generated to a temporary directory and removed afterwards.
+
+use std::path::PathBuf;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use rotuli::Site;
+
+const PAGES: usize = 5000;
+const SECTIONS: usize = 50;
+const TAGS: usize = 500;
+const TAGS_PER_PAGE: usize = 5;
+
+fn synthetic_site() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rotuli-bench-{}", std::process::id()));
+    for i in 0..PAGES {
+        let path = dir.join(format!("section{}/page{}.md", i % SECTIONS, i));
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let tags = (0..TAGS_PER_PAGE)
+            .map(|t| format!("tag{}: true\n", (i * 7 + t * 101) % TAGS))
+            .collect::<String>();
+        let text = format!("template: page.html\nlanguage: en\nblog: true\n{}\n# Page {}\n\nText of page {}.\n",
+            tags, i, i);
+        std::fs::write(&path, text).unwrap();
+    }
+    dir
+}
+
+fn site(c: &mut Criterion) {
+    let dir = synthetic_site();
+
+    c.bench_function("load", |b| b.iter(|| Site::builder(&dir).build().unwrap()));
+
+    let site = Site::builder(&dir).build().unwrap();
+    let urls = site.enumerate_pages().map(|(_, p)| p.display_url()).collect::<Vec<_>>();
+    let names = site.enumerate_groups().map(|(_, g)| g.name().to_owned()).collect::<Vec<_>>();
+
+    c.bench_function("groups_for every page", |b| b.iter(|| {
+        site.enumerate_pages().map(|(p, _)| site.groups_for(p).len()).sum::<usize>()
+    }));
+    c.bench_function("group_pages of every group", |b| b.iter(|| {
+        site.enumerate_groups().map(|(g, _)| site.group_pages(g).count()).sum::<usize>()
+    }));
+    c.bench_function("get_group by every name", |b| b.iter(|| {
+        names.iter().filter(|name| site.get_group(name).is_some()).count()
+    }));
+    c.bench_function("page_by_url of every page", |b| b.iter(|| {
+        urls.iter().filter(|url| site.page_by_url(url).is_some()).count()
+    }));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = site
+}
+criterion_main!(benches);
@@ -0,0 +1,115 @@
+use std::path::{Path, PathBuf};
+use std::fmt;
+
+/// A position in a source file, both counting from 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SourceLocation {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl SourceLocation {
+    pub fn new(line: usize, column: usize) -> Self {
+        SourceLocation { line, column }
+    }
+}
+
+/// Everything that can go wrong when loading or rendering a site.
+///
+/// Each error knows the file it is about, so that it can be presented like a compiler diagnostic.
+#[derive(Debug)]
+pub enum RotuliError {
+    /// A file could not be read or written.
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// The metadata header is missing, malformed, or has a bad value for a magic key.
+    Metadata {
+        path: PathBuf,
+        location: Option<SourceLocation>,
+        message: String,
+    },
+    /// The markup body did not parse or has a structure that rotuli does not understand.
+    Markup {
+        path: PathBuf,
+        location: Option<SourceLocation>,
+        message: String,
+    },
+    /// The page template failed to render.
+    Template {
+        path: PathBuf,
+        message: String,
+    },
+    /// A rendered page links to something that is not in the output.
+    BrokenLink {
+        path: PathBuf,
+        /// The rendered file, relative to the output directory; a source may have many.
+        output: PathBuf,
+        link: String,
+        message: String,
+    },
+    /// More than one source file would be rendered to the same url.
+    DuplicateUrl {
+        url: String,
+        paths: Vec<PathBuf>,
+    },
+}
+
+impl RotuliError {
+    /// The file that this error is about; the first one of them for duplicates.
+    pub fn path(&self) -> &Path {
+        match self {
+            RotuliError::Io { path, .. } => path,
+            RotuliError::Metadata { path, .. } => path,
+            RotuliError::Markup { path, .. } => path,
+            RotuliError::Template { path, .. } => path,
+            RotuliError::BrokenLink { path, .. } => path,
+            RotuliError::DuplicateUrl { paths, .. } => &paths[0],
+        }
+    }
+
+    pub fn location(&self) -> Option<SourceLocation> {
+        match self {
+            RotuliError::Metadata { location, .. } => *location,
+            RotuliError::Markup { location, .. } => *location,
+            _ => None,
+        }
+    }
+
+    /// Human-readable description without the file and location.
+    pub fn message(&self) -> String {
+        match self {
+            RotuliError::Io { source, .. } => source.to_string(),
+            RotuliError::Metadata { message, .. } => message.clone(),
+            RotuliError::Markup { message, .. } => message.clone(),
+            RotuliError::Template { message, .. } => message.clone(),
+            RotuliError::BrokenLink { output, link, message, .. } =>
+                format!("broken link to {} in {}: {}", link, output.to_string_lossy(), message),
+            RotuliError::DuplicateUrl { url, paths } => format!("url {} is produced by all of: {}",
+                url, paths.iter().map(|p| p.to_string_lossy()).collect::<Vec<_>>().join(", ")),
+        }
+    }
+}
+
+// path:line:column: message, like most compilers do it
+impl fmt::Display for RotuliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path().to_string_lossy())?;
+        if let Some(loc) = self.location() {
+            write!(f, ":{}:{}", loc.line, loc.column)?;
+        }
+        write!(f, ": {}", self.message())
+    }
+}
+
+impl std::error::Error for RotuliError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RotuliError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, RotuliError>;
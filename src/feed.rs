@@ -0,0 +1,291 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Deserialize;
+
+use crate::metadata::{Metadata, MAGIC_META_FEED, MAGIC_META_PAGINATE, MAGIC_META_TAXONOMY,
+    MAGIC_META_TEMPLATE};
+use crate::page::Page;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedFormat {
+    /// RSS 2.0
+    Rss,
+    Atom,
+}
+
+/// Renders a page as a feed of the newest pages of a group instead of through a template, as in
+///
+/// ```yaml
+/// feed: { format: rss, group: blog, filter: { language: en }, limit: 20 }
+/// ```
+///
+/// The feed gets its title from the page and the items are dated by `created_at` or another
+/// metadata key given as `date`; pages without a date are left out. Links are absolute with the
+/// base url of the build.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Feed {
+    pub format: FeedFormat,
+    /// The pages of this group are the items.
+    pub group: String,
+    /// Only the pages that have these metadata values are included, as with taxonomies.
+    #[serde(default)]
+    pub filter: BTreeMap<String, String>,
+    /// Metadata key of the item dates, as in "2016-09-14" or "2016-09-14T12:00:00+03:00".
+    #[serde(default = "default_date_key")]
+    pub date: String,
+    /// At most this many of the newest items.
+    #[serde(default)]
+    pub limit: Option<usize>,
+    /// Required by Atom; the title of the page by default.
+    #[serde(default)]
+    pub author: Option<String>,
+}
+
+fn default_date_key() -> String {
+    "created_at".to_owned()
+}
+
+impl Feed {
+    pub(crate) fn from_metadata(metadata: &Metadata) -> Result<Option<Self>, String> {
+        let value = match metadata.get(MAGIC_META_FEED) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let feed = serde_yaml::from_value::<Feed>(value.clone())
+            .map_err(|e| format!("bad `{}': {}", MAGIC_META_FEED, e))?;
+        for key in &[MAGIC_META_TEMPLATE, MAGIC_META_PAGINATE, MAGIC_META_TAXONOMY] {
+            if metadata.contains_key(key) {
+                return Err(format!("`{}' cannot be used with `{}', feeds are rendered without templates",
+                    MAGIC_META_FEED, key));
+            }
+        }
+        Ok(Some(feed))
+    }
+
+    /// Whether a page with this metadata is an item, if it has a date.
+    pub fn includes(&self, metadata: &Metadata) -> bool {
+        metadata.lookup(&self.group).is_some() && metadata.matches(&self.filter)
+    }
+}
+
+/// Seconds since the Unix epoch, from a metadata date.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct Timestamp(i64);
+
+impl From<SystemTime> for Timestamp {
+    fn from(time: SystemTime) -> Self {
+        match time.duration_since(UNIX_EPOCH) {
+            Ok(after) => Timestamp(after.as_secs() as i64),
+            Err(before) => Timestamp(-(before.duration().as_secs() as i64)),
+        }
+    }
+}
+
+// days since 1970-01-01 of a proleptic Gregorian date, and back again; see
+// http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (yoe + era * 400 + if month <= 2 { 1 } else { 0 }, month, day)
+}
+
+/// Parse a date as in YAML and TOML metadata: "2016-09-14", optionally followed by a time
+/// after a "T" or a space, and a "Z" or a "+03:00" style offset; without one, the time is UTC.
+pub(crate) fn parse_date(s: &str) -> Option<Timestamp> {
+    let number = |part: &str| -> Option<i64> {
+        if !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()) { part.parse().ok() } else { None }
+    };
+    let (date, rest) = s.split_at(s.find(['T', 't', ' ']).unwrap_or(s.len()));
+    let mut date = date.splitn(3, '-');
+    let (year, month, day) = (number(date.next()?)?, number(date.next()?)?, number(date.next()?)?);
+    let days_in_month = match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        1..=12 => 31,
+        _ => return None,
+    };
+    if day < 1 || day > days_in_month {
+        return None;
+    }
+
+    let rest = rest.get(1..).unwrap_or("").trim();
+    let (time, offset) = match rest.find(['Z', 'z', '+', '-']) {
+        Some(i) => rest.split_at(i),
+        None => (rest, ""),
+    };
+    let mut seconds = 0;
+    if !time.is_empty() {
+        // fractions of a second do not matter in feeds
+        let time = time.split('.').next()?;
+        let mut parts = time.split(':');
+        let (hour, minute) = (number(parts.next()?)?, number(parts.next()?)?);
+        let second = parts.next().map_or(Some(0), number)?;
+        if parts.next().is_some() || hour > 23 || minute > 59 || second > 60 {
+            return None;
+        }
+        seconds = hour * 3600 + minute * 60 + second;
+    }
+    let offset = match offset {
+        "" | "Z" | "z" => 0,
+        _ => {
+            let sign = if offset.starts_with('-') { -1 } else { 1 };
+            let digits = offset[1..].replace(':', "");
+            if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            sign * (number(&digits[..2])? * 3600 + number(&digits[2..])? * 60)
+        }
+    };
+    Some(Timestamp(days_from_civil(year, month, day) * 86400 + seconds - offset))
+}
+
+// "Wed, 14 Sep 2016 00:00:00 +0000"
+fn rfc822(t: Timestamp) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let (days, seconds) = (t.0.div_euclid(86400), t.0.rem_euclid(86400));
+    let (year, month, day) = civil_from_days(days);
+    format!("{}, {:02} {} {} {:02}:{:02}:{:02} +0000", WEEKDAYS[days.rem_euclid(7) as usize], day,
+        MONTHS[month as usize - 1], year, seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+// "2016-09-14T00:00:00Z"
+pub(crate) fn rfc3339(t: Timestamp) -> String {
+    let (days, seconds) = (t.0.div_euclid(86400), t.0.rem_euclid(86400));
+    let (year, month, day) = civil_from_days(days);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day,
+        seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+pub(crate) fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The XML text of the feed page `page` with the given items, newest first.
+pub(crate) fn render(feed: &Feed, page: &Page, items: &[(&Page, Timestamp)], base_url: &str) -> String {
+    let base_url = base_url.trim_end_matches('/');
+    let absolute = |p: &Page| escape(&format!("{}{}", base_url, p.display_url()));
+    let title = escape(page.title());
+    let self_url = absolute(page);
+    let home_url = escape(&format!("{}/", base_url));
+
+    // writing to a string does not fail
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    match feed.format {
+        FeedFormat::Rss => {
+            let description = match page.summary_rendered() {
+                "" => title.clone(),
+                summary => escape(summary),
+            };
+            xml.push_str("<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\">\n<channel>\n");
+            let _ = writeln!(xml, "<title>{}</title>\n<link>{}</link>\n<description>{}</description>",
+                title, home_url, description);
+            let _ = writeln!(xml, "<atom:link href=\"{}\" rel=\"self\" type=\"application/rss+xml\"/>", self_url);
+            if let Some((_, newest)) = items.first() {
+                let _ = writeln!(xml, "<lastBuildDate>{}</lastBuildDate>", rfc822(*newest));
+            }
+            for (p, date) in items {
+                let url = absolute(p);
+                let _ = writeln!(xml, "<item>\n<title>{}</title>\n<link>{}</link>\n\
+                    <guid isPermaLink=\"true\">{}</guid>\n<pubDate>{}</pubDate>\n\
+                    <description>{}</description>\n</item>",
+                    escape(p.title()), url, url, rfc822(*date), escape(p.summary_rendered()));
+            }
+            xml.push_str("</channel>\n</rss>\n");
+        }
+        FeedFormat::Atom => {
+            let author = feed.author.as_deref().map_or_else(|| title.clone(), escape);
+            let updated = items.first().map_or(Timestamp(0), |&(_, date)| date);
+            xml.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+            let _ = writeln!(xml, "<title>{}</title>\n<id>{}</id>\n<link href=\"{}\" rel=\"self\"/>\n\
+                <link href=\"{}\"/>\n<updated>{}</updated>\n<author><name>{}</name></author>",
+                title, self_url, self_url, home_url, rfc3339(updated), author);
+            for (p, date) in items {
+                let url = absolute(p);
+                let _ = writeln!(xml, "<entry>\n<title>{}</title>\n<id>{}</id>\n<link href=\"{}\"/>\n\
+                    <updated>{}</updated>\n<summary type=\"html\">{}</summary>\n</entry>",
+                    escape(p.title()), url, url, rfc3339(*date), escape(p.summary_rendered()));
+            }
+            xml.push_str("</feed>\n");
+        }
+    }
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn civil_days_round_trip() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+        for days in (-800_000..800_000).step_by(97) {
+            let (y, m, d) = civil_from_days(days);
+            assert_eq!(days_from_civil(y, m, d), days);
+        }
+    }
+
+    #[test]
+    fn leap_years() {
+        assert!(parse_date("2016-02-29").is_some());
+        assert!(parse_date("2000-02-29").is_some());
+        assert!(parse_date("1900-02-29").is_none());
+        assert!(parse_date("2019-02-29").is_none());
+        assert_eq!(parse_date("2016-03-01").unwrap().0 - parse_date("2016-02-28").unwrap().0, 2 * 86400);
+        assert_eq!(civil_from_days(days_from_civil(2020, 2, 29)), (2020, 2, 29));
+    }
+
+    #[test]
+    fn offsets() {
+        let utc = parse_date("2016-09-14T12:00:00Z").unwrap();
+        assert_eq!(parse_date("2016-09-14T12:00:00"), Some(utc));
+        assert_eq!(parse_date("2016-09-14 15:00:00+03:00"), Some(utc));
+        assert_eq!(parse_date("2016-09-14T07:30-0430"), Some(utc));
+        assert_eq!(rfc3339(parse_date("2016-09-14T01:00:00+03:00").unwrap()), "2016-09-13T22:00:00Z");
+        assert_eq!(parse_date("2016-09-14T12:00+3"), None);
+        assert_eq!(parse_date("2020-01-01T10:00+a\u{20ac}"), None);
+        assert_eq!(parse_date("2020-01-01T10:00+\u{20ac}00"), None);
+    }
+
+    #[test]
+    fn formats() {
+        let t = parse_date("2016-09-14").unwrap();
+        assert_eq!(rfc822(t), "Wed, 14 Sep 2016 00:00:00 +0000");
+        assert_eq!(rfc3339(t), "2016-09-14T00:00:00Z");
+        assert_eq!(rfc822(parse_date("1969-12-31T23:59:59Z").unwrap()), "Wed, 31 Dec 1969 23:59:59 +0000");
+        assert_eq!(rfc822(parse_date("2000-02-29T08:05:09Z").unwrap()), "Tue, 29 Feb 2000 08:05:09 +0000");
+    }
+}
@@ -0,0 +1,60 @@
+//! Custom Tera filters that make the site structure easier to handle in templates.
+
+use std::collections::HashMap;
+
+use tera::Tera;
+
+/// Register all rotuli filters to the given template engine.
+pub fn register(tera: &mut Tera) {
+    tera.register_filter("flatten_array", flatten_array);
+    tera.register_filter("take_until_attr", take_until_attr);
+}
+
+// Array([Array([String("x")]), Array([String("y")]), Array([String("z"), String("w")])
+// into Array([String("x"), String("y"), String("z"), String("w")])
+pub fn flatten_array(value: &tera::Value, _args: &HashMap<String, tera::Value>)
+-> tera::Result<tera::Value> {
+    if let tera::Value::Array(items) = value {
+        let mut flattened = Vec::new();
+        for inner in items {
+            match inner {
+                tera::Value::Array(inner) => flattened.extend(inner.iter().cloned()),
+                other => return Err(tera::Error::msg(format!(
+                    "trying to flatten an array that contains other than just arrays: {}", other))),
+            }
+        }
+        Ok(tera::Value::Array(flattened))
+    } else {
+        Err(tera::Error::msg("trying to flatten something that's not an array"))
+    }
+}
+
+fn get_json_pointer(key: &str) -> String {
+    ["/", &key.replace(".", "/")].join("")
+}
+
+pub fn take_until_attr(value: &tera::Value, args: &HashMap<String, tera::Value>)
+-> tera::Result<tera::Value> {
+    let arr = tera::try_get_value!("take_until_attr", "value", Vec<tera::Value>, value);
+    if arr.is_empty() {
+        return Ok(tera::Value::Null);
+    }
+
+    let key = match args.get("attribute") {
+        Some(val) => tera::try_get_value!("take_until_attr", "attribute", String, val),
+        None => return Err(tera::Error::msg("The `take_until_attr` filter has to have an `attribute` argument")),
+    };
+
+    let val_lookup = match args.get("value") {
+        Some(val) => val,
+        None => return Err(tera::Error::msg("The `take_until_attr` filter has to have an `value` argument")),
+    };
+
+    let json_pointer = get_json_pointer(&key);
+
+    let value = arr.iter()
+        .take_while(|&x| x.pointer(&json_pointer).is_some_and(|value| value != val_lookup))
+        .collect::<Vec<_>>();
+
+    Ok(tera::to_value(value).expect("couldn't re-value an array of Tera values??"))
+}
@@ -0,0 +1,230 @@
+// Splitting a source file into its metadata header and the markup content.
+//
+// The header can be written in three ways:
+//
+//  * plain YAML that ends at the first empty line (the original rotuli format)
+//  * YAML between lines of "---" (and "---" or "..."), so it can have empty lines
+//  * TOML between lines of "+++"
+//
+// A file that starts with an empty line has no header at all and gets just the defaults.
+
+use regex::Regex;
+
+use crate::error::SourceLocation;
+use crate::metadata::{Metadata, MAGIC_META_FEED, MAGIC_META_TEMPLATE};
+
+pub(crate) struct FrontMatter<'a> {
+    pub metadata: Metadata,
+    pub content: &'a str,
+    /// How many lines there are before the content starts.
+    pub content_line: usize,
+}
+
+/// A metadata problem with a location relative to the whole file.
+pub(crate) struct FrontMatterError {
+    pub location: Option<SourceLocation>,
+    pub message: String,
+}
+
+impl FrontMatterError {
+    fn new(location: Option<SourceLocation>, message: &str) -> Self {
+        FrontMatterError { location, message: message.to_owned() }
+    }
+}
+
+/// Strip the byte order mark and convert CRLF line endings, so that the rest can assume '\n'.
+pub(crate) fn normalize(data: String) -> String {
+    let data = data.strip_prefix('\u{feff}').map(|s| s.to_owned()).unwrap_or(data);
+    if data.contains("\r\n") {
+        data.replace("\r\n", "\n")
+    } else {
+        data
+    }
+}
+
+// lines with their starting byte offsets, including the newline
+fn lines_with_offsets(data: &str) -> impl Iterator<Item = (usize, &str)> {
+    data.split_inclusive('\n').scan(0, |pos, line| {
+        let start = *pos;
+        *pos += line.len();
+        Some((start, line))
+    })
+}
+
+// skip empty lines after a header so that the markup doesn't start with them
+fn skip_blank_lines(data: &str, mut pos: usize, mut line: usize) -> (usize, usize) {
+    for (_, l) in lines_with_offsets(&data[pos..]) {
+        if !l.trim().is_empty() {
+            break;
+        }
+        pos += l.len();
+        line += 1;
+    }
+    (pos, line)
+}
+
+pub(crate) fn yaml_error(e: serde_yaml::Error, header_line: usize) -> FrontMatterError {
+    // the location is reported separately, don't repeat it in the message
+    let message = Regex::new(r" at line \d+ column \d+$").expect("bad regex")
+        .replace(&e.to_string(), "").into_owned();
+    let location = e.location().map(|l| SourceLocation::new(l.line() + header_line, l.column()));
+    FrontMatterError { location, message }
+}
+
+fn toml_error(e: toml::de::Error, header: &str, header_line: usize) -> FrontMatterError {
+    let location = e.span().map(|span| {
+        let before = &header[..span.start];
+        let line = before.matches('\n').count() + 1;
+        let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+        SourceLocation::new(line + header_line, column)
+    });
+    FrontMatterError { location, message: e.message().to_owned() }
+}
+
+fn parse_yaml(header: &str, header_line: usize) -> Result<Metadata, FrontMatterError> {
+    if header.trim().is_empty() {
+        return Ok(Metadata::empty());
+    }
+    Metadata::from_string(header).map_err(|e| yaml_error(e, header_line))
+}
+
+// A header between delimiter lines, e.g., "+++"; the opening one is known to be there
+fn parse_delimited<'a>(data: &'a str, closing: &[&str], format: &str)
+-> Result<(&'a str, &'a str, usize), FrontMatterError> {
+    let mut lines = lines_with_offsets(data).enumerate();
+    let (_, (_, opening)) = lines.next().expect("the opening delimiter was found");
+    let header_start = opening.len();
+    for (i, (start, line)) in lines {
+        if closing.contains(&line.trim_end()) {
+            let (content_start, content_line) = skip_blank_lines(data, start + line.len(), i + 1);
+            return Ok((&data[header_start..start], &data[content_start..], content_line));
+        }
+    }
+    Err(FrontMatterError::new(Some(SourceLocation::new(1, 1)),
+        &format!("{} metadata block is not closed", format)))
+}
+
+// The content of a file without metadata may well have been taken for an undelimited header;
+// when the header does not look like one, say how to have none
+fn with_hint(mut e: FrontMatterError) -> FrontMatterError {
+    e.message += "; start the file with an empty line if it has no metadata";
+    e
+}
+
+// whether the header starts with a "key:" line, as opposed to, e.g., markup
+fn starts_with_key(header: &str) -> bool {
+    Regex::new(r#"^(\w[\w.-]*|"[^"\n]*"|'[^'\n]*') *:(\s|$)"#).expect("bad regex").is_match(header)
+}
+
+pub(crate) fn parse(data: &str) -> Result<FrontMatter<'_>, FrontMatterError> {
+    let first_line = data.lines().next().unwrap_or("").trim_end();
+
+    let (metadata, content, content_line) = match first_line {
+        "---" => {
+            let (header, content, content_line) = parse_delimited(data, &["---", "..."], "YAML")?;
+            (parse_yaml(header, 1)?, content, content_line)
+        }
+        "+++" => {
+            let (header, content, content_line) = parse_delimited(data, &["+++"], "TOML")?;
+            let metadata = Metadata::from_toml(header).map_err(|e| toml_error(e, header, 1))?;
+            (metadata, content, content_line)
+        }
+        "" => {
+            // no header, so defaults only
+            let (content_start, content_line) = skip_blank_lines(data, 0, 0);
+            (Metadata::empty(), &data[content_start..], content_line)
+        }
+        _ => {
+            let split_pos = data.find("\n\n").ok_or_else(|| with_hint(FrontMatterError::new(None,
+                "missing metadata separator (an empty line after the metadata)")))?;
+            let header = &data[..split_pos];
+            let metadata = parse_yaml(header, 0).map_err(|e| {
+                if starts_with_key(header) { e } else { with_hint(e) }
+            })?;
+            let (content_start, content_line) = skip_blank_lines(data, split_pos + 1,
+                data[..split_pos + 1].matches('\n').count());
+            (metadata, &data[content_start..], content_line)
+        }
+    };
+
+    Ok(FrontMatter { metadata, content, content_line })
+}
+
+/// Whether the data clearly starts with metadata, as opposed to content only. A delimited header
+/// counts even if it is broken, so that its errors get reported; an undelimited one only if it
+/// names a template or a feed, as the first paragraph of plain HTML may well parse as YAML.
+pub(crate) fn has_metadata(data: &str) -> bool {
+    let first_line = data.lines().next().unwrap_or("").trim_end();
+    first_line == "---" || first_line == "+++"
+        || parse(data).is_ok_and(|f| f.metadata.contains_key(MAGIC_META_TEMPLATE)
+            || f.metadata.contains_key(MAGIC_META_FEED))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_ok(data: &str) -> FrontMatter<'_> {
+        parse(data).unwrap_or_else(|e| panic!("{:?} failed: {}", data, e.message))
+    }
+
+    #[test]
+    fn undelimited_yaml() {
+        let f = parse_ok("title: x\ntemplate: t.html\n\n\nhello\n");
+        assert_eq!(f.metadata.get("title").unwrap().as_str(), Some("x"));
+        assert_eq!(f.content, "hello\n");
+        assert_eq!(f.content_line, 4);
+    }
+
+    #[test]
+    fn crlf_and_bom() {
+        let data = normalize("\u{feff}---\r\ntitle: x\r\n---\r\n\r\nhello\r\n".to_owned());
+        let f = parse_ok(&data);
+        assert_eq!(f.metadata.get("title").unwrap().as_str(), Some("x"));
+        assert_eq!(f.content, "hello\n");
+        assert_eq!(f.content_line, 4);
+    }
+
+    #[test]
+    fn delimited_yaml_with_blank_lines() {
+        let f = parse_ok("---\ntitle: x\n\ntags: [a]\n...\nhello\n");
+        assert_eq!(f.metadata.get("title").unwrap().as_str(), Some("x"));
+        assert!(f.metadata.contains_key("tags"));
+        assert_eq!(f.content, "hello\n");
+        assert_eq!(f.content_line, 5);
+    }
+
+    #[test]
+    fn toml() {
+        let f = parse_ok("+++\ntitle = \"x\"\ncreated_at = 2016-09-14\n+++\n\nhello\n");
+        assert_eq!(f.metadata.get("title").unwrap().as_str(), Some("x"));
+        assert_eq!(f.metadata.lookup("created_at.year").unwrap().as_str(), Some("2016"));
+        assert_eq!(f.content, "hello\n");
+        assert_eq!(f.content_line, 5);
+    }
+
+    #[test]
+    fn no_header() {
+        let f = parse_ok("\n\nhello: there\n\nworld\n");
+        assert!(f.metadata.data().is_empty());
+        assert_eq!(f.content, "hello: there\n\nworld\n");
+        assert_eq!(f.content_line, 2);
+    }
+
+    #[test]
+    fn error_locations() {
+        let e = parse("---\ntitle: x\ntags: [a\n---\n").err().unwrap();
+        assert_eq!(e.location.map(|l| (l.line, l.column)), Some((4, 1)));
+        let e = parse("+++\ntitle = \"x\"\ntags = \n+++\n").err().unwrap();
+        assert_eq!(e.location.map(|l| (l.line, l.column)), Some((3, 8)));
+        assert!(parse("---\ntitle: x\n").is_err());
+    }
+
+    #[test]
+    fn hint_only_when_not_a_header() {
+        let hint = "start the file with an empty line";
+        assert!(!parse("title: [oops\n\nhello\n").err().unwrap().message.contains(hint));
+        assert!(parse("<p>a: b</p>\n<p>c</p>\n\nhello\n").err().unwrap().message.contains(hint));
+        assert!(parse("title: x\nhello\n").err().unwrap().message.contains(hint));
+    }
+}
@@ -0,0 +1,35 @@
+use std::fmt;
+
+use crate::page::PageReference;
+
+/// An index to a group in a `Site`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GroupReference(pub(crate) usize);
+
+/// All pages that share a metadata key, named by that key. Keys of nested mappings and the year
+/// and month of dates make groups too, named by their dotted paths such as `created_at.year`.
+pub struct Group {
+    name: String,
+    pages: Vec<PageReference>,
+}
+
+impl Group {
+    pub(crate) fn new(name: &str, pages: Vec<PageReference>) -> Self {
+        Group { name: name.to_owned(), pages }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn pages(&self) -> &[PageReference] {
+        &self.pages
+    }
+}
+
+impl fmt::Debug for Group {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Group {{ name: {}, pages: {:?} }}", self.name,
+               self.pages)
+    }
+}
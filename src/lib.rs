@@ -0,0 +1,55 @@
+//! Rotuli, the most universal content processor for static websites and the like.
+//!
+//! Load a source directory with a `SiteBuilder`, then `render` the pages through Tera templates
+//! and copy the plain files over. The `BuildState` of the output directory remembers what was
+//! built from what, so that the next build only redoes what has changed:
+//!
+//! ```no_run
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let site = rotuli::Site::builder("sample-source").build()?;
+//! let tera = rotuli::load_templates(&["sample-templates"])?;
+//! let mut state = rotuli::BuildState::load("out")?;
+//! site.render(&tera, "http://localhost", &mut state)?;
+//! site.copy_plain_files(&mut state)?;
+//! state.save()?;
+//! # Ok(())
+//! # }
+//! ```
+
+mod error;
+mod metadata;
+mod frontmatter;
+mod markup;
+mod page;
+mod group;
+mod pagination;
+mod references;
+mod taxonomy;
+mod feed;
+mod sitemap;
+mod links;
+mod schema;
+mod plain;
+mod site;
+mod state;
+mod templates;
+pub mod filters;
+
+// RenderedMarkup and Page expose rst documents
+pub use document_tree;
+
+pub use crate::error::{Result, RotuliError, SourceLocation};
+pub use crate::metadata::{value_strings, Metadata, MetadataValue};
+pub use crate::markup::{CommandRenderer, MarkupError, MarkupLanguage, MarkupLanguageParseError,
+    MarkupRenderer, RenderedMarkup};
+pub use crate::page::{Page, PageReference};
+pub use crate::group::{Group, GroupReference};
+pub use crate::pagination::Pagination;
+pub use crate::site::{RenderedPage, Site, SiteBuilder};
+pub use crate::taxonomy::Taxonomy;
+pub use crate::feed::{Feed, FeedFormat};
+pub use crate::sitemap::Sitemap;
+pub use crate::links::check_links;
+pub use crate::plain::{LinkMode, LinkModeParseError};
+pub use crate::state::BuildState;
+pub use crate::templates::load_templates;
@@ -0,0 +1,214 @@
+// Checking that the links between rendered pages lead somewhere. The html is not parsed properly,
+// just scanned for the attributes that matter, which is enough for what templates produce.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::Path;
+
+use rayon::prelude::*;
+use regex::Regex;
+
+use crate::error::RotuliError;
+use crate::state::BuildState;
+
+// what a rendered html file links to, and the anchors that can be linked to in it
+struct Scanned {
+    links: BTreeSet<String>,
+    anchors: HashSet<String>,
+}
+
+fn scan(html: &str, attributes: &Regex, comments: &Regex) -> Scanned {
+    let html = comments.replace_all(html, "");
+    let mut scanned = Scanned { links: BTreeSet::new(), anchors: HashSet::new() };
+    for cap in attributes.captures_iter(&html) {
+        let value = cap.get(2).or_else(|| cap.get(3)).or_else(|| cap.get(4)).map_or("", |m| m.as_str());
+        let value = decode_entities(value.trim());
+        match cap[1].to_ascii_lowercase().as_str() {
+            "href" | "src" => { scanned.links.insert(value); }
+            _ => { scanned.anchors.insert(value); }
+        }
+    }
+    scanned
+}
+
+// the few named ones that templates escape, and the numeric ones; others are kept as they are
+fn decode_entities(s: &str) -> String {
+    let mut decoded = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(amp) = rest.find('&') {
+        decoded.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let entity = rest.find(';').filter(|&end| end < 12).map(|end| (&rest[1..end], end));
+        let c = entity.and_then(|(name, _)| match name {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => name.strip_prefix("#x").or_else(|| name.strip_prefix("#X"))
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| name.strip_prefix('#').map(|dec| dec.parse()))
+                .and_then(|n| n.ok())
+                .and_then(char::from_u32),
+        });
+        match (c, entity) {
+            (Some(c), Some((_, end))) => {
+                decoded.push(c);
+                rest = &rest[end + 1..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (b, _) => {
+                decoded.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// "a/b/index.html" is served at "/a/b/", the rest as they are
+fn output_url(output: &str) -> String {
+    match output.strip_suffix("index.html") {
+        Some(dir) if dir.is_empty() || dir.ends_with('/') => format!("/{}", dir),
+        _ => format!("/{}", output),
+    }
+}
+
+// The site path and the fragment of a link from a page at `page_url`, or none for links out of
+// the site. Links that start with the base url are in the site too.
+fn resolve(link: &str, page_url: &str, base_url: &str) -> Option<(String, String)> {
+    let link = match link.strip_prefix(base_url).filter(|_| !base_url.is_empty()) {
+        Some("") => "/",
+        Some(rest) if rest.starts_with('/') => rest,
+        _ => link,
+    };
+    let has_scheme = link.find(':').is_some_and(|colon| {
+        link[..colon].chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+            && link[..colon].starts_with(|c: char| c.is_ascii_alphabetic())
+    });
+    if has_scheme || link.starts_with("//") {
+        return None;
+    }
+
+    let (link, fragment) = link.split_once('#').unwrap_or((link, ""));
+    let path = link.split('?').next().unwrap_or("");
+    let joined = if path.is_empty() {
+        page_url.to_owned()
+    } else if path.starts_with('/') {
+        path.to_owned()
+    } else {
+        format!("{}{}", &page_url[..page_url.rfind('/').expect("urls start with a slash") + 1], path)
+    };
+
+    let mut segments = Vec::new();
+    for segment in joined.split('/').skip(1) {
+        match segment {
+            "." => (),
+            ".." => { segments.pop(); }
+            s => segments.push(s),
+        }
+    }
+    // a trailing "." or ".." still means a directory
+    let directory = joined.ends_with('/') || joined.ends_with("/.") || joined.ends_with("/..");
+    if directory && segments.last() != Some(&"") {
+        segments.push("");
+    }
+    Some((percent_decode(&format!("/{}", segments.join("/"))), percent_decode(fragment)))
+}
+
+/// Read the html files written by a build and find the links in them that do not lead to a file
+/// of the build, or to an anchor that is not in the linked page. Links to other sites are not
+/// checked; links that start with `base_url` are treated as links within the site.
+///
+/// The errors are given at the source files of the pages, with the rendered file in each.
+pub fn check_links(state: &BuildState, base_url: &str) -> Vec<RotuliError> {
+    let attributes = Regex::new(r#"(?i)\s(href|src|id|name)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#)
+        .expect("bad link regex");
+    let comments = Regex::new(r"(?s)<!--.*?-->").expect("bad comment regex");
+    let base_url = base_url.trim_end_matches('/');
+
+    let outputs = state.outputs()
+        .filter_map(|(output, source)| output.to_str().map(|o| (o.replace('\\', "/"), source)))
+        .collect::<Vec<_>>();
+    let files = outputs.iter().map(|(output, _)| output.as_str()).collect::<HashSet<_>>();
+    let pages = outputs.par_iter()
+        .filter(|(output, _)| output.ends_with(".html"))
+        .map(|(output, source)| {
+            let path = state.output_dir().join(output);
+            std::fs::read_to_string(&path)
+                .map(|html| (output.as_str(), *source, scan(&html, &attributes, &comments)))
+                .map_err(|e| RotuliError::Io { path, source: e })
+        })
+        .collect::<Vec<_>>();
+
+    let mut errors = Vec::new();
+    let mut scanned_pages = HashMap::new();
+    for page in pages {
+        match page {
+            Ok((output, source, scanned)) => { scanned_pages.insert(output, (source, scanned)); }
+            Err(e) => errors.push(e),
+        }
+    }
+
+    let mut by_output = scanned_pages.iter().collect::<Vec<_>>();
+    by_output.sort_by_key(|(output, _)| **output);
+    for (output, (source, scanned)) in by_output {
+        let page_url = output_url(output);
+        let broken = |link: &str, message: String| RotuliError::BrokenLink {
+            path: source.to_path_buf(),
+            output: Path::new(output).to_owned(),
+            link: link.to_owned(),
+            message,
+        };
+        for link in &scanned.links {
+            let (path, fragment) = match resolve(link, &page_url, base_url) {
+                Some(resolved) => resolved,
+                None => continue,
+            };
+            let relative = &path[1..];
+            let target = if relative.is_empty() || relative.ends_with('/') {
+                Some(format!("{}index.html", relative))
+            } else {
+                // a directory without the slash is fine too, servers redirect those
+                [relative.to_owned(), format!("{}/index.html", relative)].iter()
+                    .find(|candidate| files.contains(candidate.as_str())).cloned()
+            };
+            let target = match target.filter(|t| files.contains(t.as_str())) {
+                Some(target) => target,
+                None => {
+                    errors.push(broken(link, format!("{} is not a page or a file of the site", path)));
+                    continue;
+                }
+            };
+            if fragment.is_empty() || fragment == "top" {
+                continue;
+            }
+            if let Some((_, target_page)) = scanned_pages.get(target.as_str()) {
+                if !target_page.anchors.contains(&fragment) {
+                    errors.push(broken(link, format!("{} has no anchor #{}", path, fragment)));
+                }
+            }
+        }
+    }
+    errors
+}
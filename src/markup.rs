@@ -0,0 +1,120 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::SourceLocation;
+
+mod rst;
+mod markdown;
+mod org;
+mod command;
+
+pub use self::command::CommandRenderer;
+
+/// Converts markup text of some language to HTML.
+///
+/// Renderers are registered to a `SiteBuilder` by file extension. The built-in languages of
+/// `MarkupLanguage` are renderers too. Pages are loaded in parallel, so a renderer gets called
+/// from many threads at once.
+pub trait MarkupRenderer: Send + Sync {
+    fn render(&self, source: &str) -> Result<RenderedMarkup, MarkupError>;
+}
+
+/// The markup language that page sources are written in.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MarkupLanguage {
+    RestructuredText,
+    Markdown,
+    Org,
+    /// HTML or anything else that is used as-is, e.g., for pages that get all their content from
+    /// the template. The title comes from metadata only, and html files without metadata are
+    /// plain files.
+    Passthrough,
+}
+
+impl MarkupLanguage {
+    /// All the languages that rotuli knows.
+    pub fn all() -> &'static [MarkupLanguage] {
+        &[
+            MarkupLanguage::RestructuredText,
+            MarkupLanguage::Markdown,
+            MarkupLanguage::Org,
+            MarkupLanguage::Passthrough,
+        ]
+    }
+
+    pub fn from_extension(ext: &str) -> Option<MarkupLanguage> {
+        MarkupLanguage::all().iter().find(|ml| ml.as_str() == ext).cloned()
+    }
+
+    /// File extension of the source files in this language.
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            MarkupLanguage::RestructuredText => "rst",
+            MarkupLanguage::Markdown => "md",
+            MarkupLanguage::Org => "org",
+            MarkupLanguage::Passthrough => "html",
+        }
+    }
+
+}
+
+impl MarkupRenderer for MarkupLanguage {
+    fn render(&self, s: &str) -> Result<RenderedMarkup, MarkupError> {
+        match *self {
+            MarkupLanguage::RestructuredText => rst::rstrender(s),
+            MarkupLanguage::Markdown => markdown::mdrender(s),
+            MarkupLanguage::Org => org::orgrender(s),
+            MarkupLanguage::Passthrough => Ok(RenderedMarkup {
+                title: "".to_string(), summary: "".to_string(), body: s.to_string(), document: None
+            }),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct MarkupLanguageParseError;
+
+impl fmt::Display for MarkupLanguageParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown markup language, please use one of: [rst, md, org, html]")
+    }
+}
+
+impl std::error::Error for MarkupLanguageParseError {}
+
+impl FromStr for MarkupLanguage {
+    type Err = MarkupLanguageParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        MarkupLanguage::from_extension(s).ok_or(MarkupLanguageParseError)
+    }
+}
+
+impl fmt::Display for MarkupLanguage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// A problem in markup content; the location is relative to the start of the markup text.
+#[derive(Debug)]
+pub struct MarkupError {
+    pub location: Option<SourceLocation>,
+    pub message: String,
+}
+
+impl MarkupError {
+    pub(crate) fn new(message: &str) -> Self {
+        MarkupError { location: None, message: message.to_owned() }
+    }
+}
+
+/// The parts of a markup document that rotuli cares about, all rendered to HTML.
+pub struct RenderedMarkup {
+    /// Plain text, without HTML.
+    pub title: String,
+    pub summary: String,
+    pub body: String,
+    /// The parsed document if the renderer has one in the docutils model.
+    pub document: Option<document_tree::Document>,
+}
@@ -0,0 +1,103 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use regex::Regex;
+
+use super::{MarkupError, MarkupRenderer, RenderedMarkup};
+
+/// Renders markup with an external program that reads the markup from its stdin and writes an
+/// HTML fragment to its stdout, e.g., `pandoc -f textile -t html`.
+///
+/// The title is the text of the first `<h1>` and the summary is the first `<p>` of the output,
+/// which is just a heuristic; give a title in the metadata if that is not enough.
+#[derive(Debug, Clone)]
+pub struct CommandRenderer {
+    program: String,
+    args: Vec<String>,
+}
+
+impl CommandRenderer {
+    pub fn new(program: &str, args: &[&str]) -> Self {
+        CommandRenderer {
+            program: program.to_owned(),
+            args: args.iter().map(|&a| a.to_owned()).collect(),
+        }
+    }
+
+    /// Split a command line at whitespace; no shell quoting is supported.
+    pub fn from_command_line(command: &str) -> Option<Self> {
+        let mut words = command.split_whitespace();
+        let program = words.next()?;
+        Some(CommandRenderer::new(program, &words.collect::<Vec<_>>()))
+    }
+
+    fn run(&self, source: &str) -> Result<String, MarkupError> {
+        let error = |what: &str| MarkupError::new(&format!("`{}' {}", self.program, what));
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| error(&format!("could not be started: {}", e)))?;
+
+        // feed the input from another thread so that a big output can't fill the pipe and block
+        let mut stdin = child.stdin.take().expect("stdin was piped");
+        let input = source.to_owned();
+        let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+
+        let output = child.wait_with_output()
+            .map_err(|e| error(&format!("failed: {}", e)))?;
+        // a program may legitimately quit without reading everything, so this isn't fatal
+        let _ = writer.join().expect("stdin writer panicked");
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(error(&match stderr.trim() {
+                "" => format!("failed with {}", output.status),
+                msg => format!("failed with {}: {}", output.status, msg),
+            }));
+        }
+        String::from_utf8(output.stdout).map_err(|_| error("produced non-UTF-8 output"))
+    }
+}
+
+// just the basic ones so that titles look right in templates that escape them again
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+// the inner text of the first matching element, tags stripped if text_only
+fn first_element(html: &str, tag: &str, text_only: bool) -> String {
+    let element = Regex::new(&format!(r"(?s)<{0}(\s[^>]*)?>(.*?)</{0}>", tag)).expect("bad regex");
+    let tags = Regex::new(r"<[^>]*>").expect("bad regex");
+    element.captures(html)
+        .map(|c| if text_only {
+            unescape(tags.replace_all(&c[2], "").trim())
+        } else {
+            c[0].to_string()
+        })
+        .unwrap_or_default()
+}
+
+impl MarkupRenderer for CommandRenderer {
+    fn render(&self, source: &str) -> Result<RenderedMarkup, MarkupError> {
+        if source.is_empty() {
+            // same as the built-in languages; no need to bother the program
+            return Ok(RenderedMarkup {
+                title: "".to_string(), summary: "".to_string(), body: "".to_string(), document: None
+            });
+        }
+        let body = self.run(source)?;
+        Ok(RenderedMarkup {
+            title: first_element(&body, "h1", true),
+            summary: first_element(&body, "p", false),
+            body,
+            document: None,
+        })
+    }
+}
@@ -0,0 +1,54 @@
+use pulldown_cmark::{Event, Parser, Tag, TagEnd, html};
+
+use super::{MarkupError, RenderedMarkup};
+
+// Plain CommonMark; the title is the text of the first heading and the summary is the first
+// top-level paragraph, to be similar to the rst documents that have these in a section. The title
+// is not as strict as in rst because formatting can be just dropped here.
+pub fn mdrender(s: &str) -> Result<RenderedMarkup, MarkupError> {
+    let events = Parser::new(s).collect::<Vec<_>>();
+
+    let mut body = String::new();
+    html::push_html(&mut body, events.iter().cloned());
+
+    let title = first_heading_text(&events);
+    let summary = first_paragraph(&events).map(|paragraph| {
+        let mut summary = String::new();
+        html::push_html(&mut summary, paragraph.iter().cloned());
+        summary
+    }).unwrap_or_default();
+
+    Ok(RenderedMarkup { title, summary, body, document: None })
+}
+
+fn first_heading_text(events: &[Event]) -> String {
+    let start = match events.iter().position(|e| matches!(e, Event::Start(Tag::Heading { .. }))) {
+        Some(i) => i,
+        None => return "".to_string(),
+    };
+    events[start..].iter()
+        .take_while(|e| !matches!(e, Event::End(TagEnd::Heading(_))))
+        .filter_map(|e| match e {
+            Event::Text(t) | Event::Code(t) => Some(t.as_ref()),
+            _ => None,
+        })
+        .collect()
+}
+
+// the events of the first paragraph that is not nested in a list, a quote, or such
+fn first_paragraph<'a, 'e>(events: &'a [Event<'e>]) -> Option<&'a [Event<'e>]> {
+    let mut depth = 0;
+    for (i, e) in events.iter().enumerate() {
+        match e {
+            Event::Start(Tag::Paragraph) if depth == 0 => {
+                let len = events[i..].iter().position(|e| matches!(e, Event::End(TagEnd::Paragraph)))
+                    .expect("pulldown-cmark always closes the paragraphs");
+                return Some(&events[i..=i + len]);
+            }
+            Event::Start(_) => depth += 1,
+            Event::End(_) => depth -= 1,
+            _ => (),
+        }
+    }
+    None
+}
@@ -0,0 +1,82 @@
+use orgize::{Element, Event, Org};
+use orgize::export::{DefaultHtmlHandler, HtmlHandler};
+
+use super::{MarkupError, RenderedMarkup};
+
+// The default handler would wrap everything in <main>, which belongs to the templates
+#[derive(Default)]
+struct FragmentHtmlHandler(DefaultHtmlHandler);
+
+impl HtmlHandler<std::io::Error> for FragmentHtmlHandler {
+    fn start<W: std::io::Write>(&mut self, w: W, element: &Element) -> std::io::Result<()> {
+        match element {
+            Element::Document { .. } => Ok(()),
+            _ => self.0.start(w, element),
+        }
+    }
+
+    fn end<W: std::io::Write>(&mut self, w: W, element: &Element) -> std::io::Result<()> {
+        match element {
+            Element::Document { .. } => Ok(()),
+            _ => self.0.end(w, element),
+        }
+    }
+}
+
+fn render_events(events: &[Event]) -> Result<String, MarkupError> {
+    let mut handler = FragmentHtmlHandler::default();
+    let mut rendered_bytes = Vec::new();
+    for event in events {
+        match event {
+            Event::Start(element) => handler.start(&mut rendered_bytes, element),
+            Event::End(element) => handler.end(&mut rendered_bytes, element),
+        }.map_err(|e| MarkupError::new(&format!("failed to render org: {}", e)))?;
+    }
+    String::from_utf8(rendered_bytes).map_err(|_| MarkupError::new("rendered org is not valid UTF-8"))
+}
+
+// The title is #+TITLE if given, or the first headline like in markdown; the summary is the first
+// paragraph that is not nested in a list, a block or such.
+pub fn orgrender(s: &str) -> Result<RenderedMarkup, MarkupError> {
+    let org = Org::parse(s);
+    let events = org.iter().collect::<Vec<_>>();
+
+    let body = render_events(&events)?;
+
+    let title = org.keywords()
+        .find(|kw| kw.key.eq_ignore_ascii_case("title"))
+        .map(|kw| kw.value.trim().to_string())
+        .or_else(|| events.iter().find_map(|e| match e {
+            Event::Start(Element::Title(title)) => Some(title.raw.trim().to_string()),
+            _ => None,
+        }))
+        .unwrap_or_default();
+
+    let summary = match first_paragraph(&events) {
+        Some(range) => render_events(&events[range])?,
+        None => "".to_string(),
+    };
+
+    Ok(RenderedMarkup { title, summary, body, document: None })
+}
+
+fn first_paragraph(events: &[Event]) -> Option<std::ops::Range<usize>> {
+    // sections and headlines just structure the document, everything else nests
+    let structural = |e: &Element| matches!(e,
+        Element::Document { .. } | Element::Section | Element::Headline { .. });
+    let mut depth = 0;
+    for (i, e) in events.iter().enumerate() {
+        match e {
+            Event::Start(Element::Paragraph { .. }) if depth == 0 => {
+                let len = events[i..].iter()
+                    .position(|e| matches!(e, Event::End(Element::Paragraph { .. })))
+                    .expect("orgize always closes the paragraphs");
+                return Some(i..i + len + 1);
+            }
+            Event::Start(x) if !structural(x) => depth += 1,
+            Event::End(x) if !structural(x) => depth -= 1,
+            _ => (),
+        }
+    }
+    None
+}
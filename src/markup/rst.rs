@@ -0,0 +1,150 @@
+use document_tree::element_categories::HasChildren;
+use regex::Regex;
+
+use crate::error::SourceLocation;
+use super::{MarkupError, RenderedMarkup};
+
+fn top_level_rst_section(document: &document_tree::Document)
+-> Result<&document_tree::Section, MarkupError> {
+    use document_tree::{
+        element_categories as ec
+    };
+    /*
+     * Section {
+     *   common: CommonAttributes { ids: [ID("sample-website")], names: [], source: None, classes: [] },
+     *   children: [
+     *     Title {
+     *       common: CommonAttributes {
+     *         ids: [], names: [NameToken("sample-website")], source: None, classes: []
+     *       },
+     *       children: ["Sample website"]
+     *     },
+     *     Paragraph {
+     *       common: CommonAttributes { ids: [], names: [], source: None, classes: [] },
+     *       children: ["Hello world from rotuli."] },
+     */
+    // each document should be a section only for consistency; without a top level title we'd get
+    // just a bunch of paragraphs
+    if document.children().len() > 1 {
+        return Err(MarkupError::new("don't know what to do with this complex document (did you misformat or omit the title?)"));
+    }
+
+    let section_element: &ec::StructuralSubElement = document.children().first()
+        .ok_or_else(|| MarkupError::new("an empty document, you made a mistake"))?;
+    // the inner elements are boxed, hence an extra deref
+    let section_substructure: &ec::SubStructure = &**match section_element {
+        ec::StructuralSubElement::SubStructure(x) => x,
+        // title, subtitle, decoration, ...
+        _ => return Err(MarkupError::new("strange section subelement"))
+    };
+    let section_obj: &document_tree::Section = &**match section_substructure {
+        ec::SubStructure::Section(x) => x,
+        // topic, sidebar, transition, ...
+        _ => return Err(MarkupError::new("strange section substructure, do you have just one paragraph there?"))
+    };
+
+    Ok(section_obj)
+}
+
+fn document_title(document: &document_tree::Document) -> Result<String, MarkupError> {
+    use document_tree::{
+        element_categories as ec
+    };
+    let section_obj = top_level_rst_section(document)?;
+
+    let first_element = &section_obj.children().first()
+        .ok_or_else(|| MarkupError::new("how did you make a section with no content?"))?;
+
+    let titobj: &document_tree::Title = &**match first_element {
+        ec::StructuralSubElement::Title(x) => x,
+        // substructure, subtitle, decoration, ...
+        _ => return Err(MarkupError::new("only titles in the document front please"))
+    };
+    if titobj.children().len() > 1 {
+        return Err(MarkupError::new("the title is too complicated, please use just plain text"));
+    }
+    let inner_textobj: &ec::TextOrInlineElement = titobj.children().first()
+        .ok_or_else(|| MarkupError::new("how did you make a title with no content?"))?;
+
+    let title_text: &String = &**match inner_textobj {
+        ec::TextOrInlineElement::String(x) => x,
+        _ => return Err(MarkupError::new("do not format (emphasize etc.) the titles")),
+    };
+
+    Ok(title_text.to_string())
+}
+
+fn render_rst_html(document: &document_tree::Document) -> Result<String, MarkupError> {
+    let mut rendered_bytes = Vec::new();
+    rst_renderer::render_html(document, &mut rendered_bytes, false)
+        .map_err(|e| MarkupError::new(&format!("failed to render rst: {}", e)))?;
+    String::from_utf8(rendered_bytes)
+        .map_err(|_| MarkupError::new("rendered rst is not valid UTF-8"))
+}
+
+fn first_document_paragraph(document: &document_tree::Document) -> Result<String, MarkupError> {
+    use document_tree::{
+        element_categories as ec
+    };
+    let section_obj = top_level_rst_section(document)?;
+
+    // the title is expected to be at index 0; just see what immediately follows it
+    let element: Option<&ec::StructuralSubElement> = section_obj.children().get(1);
+    if let Some(element) = element {
+        if let ec::StructuralSubElement::SubStructure(x) = element {
+            // could also SubStructure(box x) = element but that's an unstable feature, will
+            // revisit. https://github.com/rust-lang/rust/issues/29641
+            if let ec::SubStructure::BodyElement(y) = &**x {
+                if let ec::BodyElement::Paragraph(_) = &**y {
+                    let doc = document_tree::Document::with_children(vec![element.clone()]);
+                    render_rst_html(&doc)
+                } else {
+                    // Perhaps a list or something, behaviour would be ambiguous for now
+                    Ok("".to_string())
+                }
+            } else {
+                // Perhaps an immediate subheading; ambiguous what to do. Recursing to its section
+                // might be a good idea, but not necessarily intended by the author.
+                Ok("".to_string())
+            }
+        } else {
+            Err(MarkupError::new("unexpected element after the title"))
+        }
+    } else {
+        // the section has no children after the title
+        Ok("".to_string())
+    }
+}
+
+// rst_parser wraps pest errors in a way that hides the position, but pest prints it like so:
+//  --> 3:5
+fn rst_parse_error(e: &dyn std::fmt::Display) -> MarkupError {
+    let text = e.to_string();
+    let position = Regex::new(r"-->\s*(\d+):(\d+)").expect("bad regex");
+    let location = position.captures(&text).map(|c| SourceLocation::new(
+            c[1].parse().expect("regex matched digits"),
+            c[2].parse().expect("regex matched digits")));
+    // the pretty-printed source excerpt would refer to markup-relative lines, so just keep the
+    // "= expected foo" part of it
+    let message = text.lines()
+        .find_map(|l| l.trim_start().strip_prefix("= "))
+        .map(|m| format!("failed to parse rst: {}", m))
+        .unwrap_or_else(|| format!("failed to parse rst: {}", text));
+    MarkupError { location, message }
+}
+
+pub fn rstrender(s: &str) -> Result<RenderedMarkup, MarkupError> {
+    if s.is_empty() {
+        // some pages might not need any body content if it comes from templates only
+        return Ok(RenderedMarkup {
+            title: "".to_string(), summary: "".to_string(), body: "".to_string(), document: None
+        })
+    }
+    let document = rst_parser::parse(s).map_err(|e| rst_parse_error(&e))?;
+    let body = render_rst_html(&document)?;
+
+    let title = document_title(&document)?;
+    let summary = first_document_paragraph(&document)?;
+
+    Ok(RenderedMarkup { title, summary, body, document: Some(document) })
+}
@@ -0,0 +1,245 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use crate::references;
+
+// Metadata keys treated in a special way; could use strings in-place, but now they're in a single
+// place here for explicitness.
+pub const MAGIC_META_TEMPLATE: &str = "template";
+pub const MAGIC_META_URL_AS_IS: &str = "url_as_is";
+pub const MAGIC_META_TITLE: &str = "title";
+pub const MAGIC_META_PAGINATE: &str = "paginate";
+pub const MAGIC_META_TAXONOMY: &str = "taxonomy";
+pub const MAGIC_META_FEED: &str = "feed";
+pub const MAGIC_META_SITEMAP: &str = "sitemap";
+
+// Keys derived from date values, e.g., `created_at.year` of `created_at: 2016-09-14`
+const DERIVED_DATE_KEYS: &[&str] = &["year", "month"];
+
+// "2016-09-14", possibly with a time after it as in TOML datetimes, to the year and the month
+fn date_parts(s: &str) -> Option<[&str; 2]> {
+    let b = s.as_bytes();
+    let digits = |from: usize, to: usize| b.get(from..to).is_some_and(|d| d.iter().all(u8::is_ascii_digit));
+    if digits(0, 4) && b.get(4) == Some(&b'-') && digits(5, 7) && b.get(7) == Some(&b'-') && digits(8, 10) {
+        Some([&s[0..4], &s[5..7]])
+    } else {
+        None
+    }
+}
+
+fn derived_value(value: &MetadataValue, key: &str) -> Option<MetadataValue> {
+    let parts = date_parts(value.as_str()?)?;
+    DERIVED_DATE_KEYS.iter().position(|&k| k == key)
+        .map(|i| MetadataValue::String(parts[i].to_owned()))
+}
+
+// the paths under this value in metadata order, depth first; page references are leaves
+fn collect_paths(value: &MetadataValue, path: &str, paths: &mut Vec<String>) {
+    match value {
+        MetadataValue::Mapping(m) if !references::is_reference(value) => {
+            for (k, v) in m {
+                if let Some(k) = k.as_str() {
+                    let subpath = format!("{}.{}", path, k);
+                    paths.push(subpath.clone());
+                    collect_paths(v, &subpath, paths);
+                }
+            }
+        }
+        MetadataValue::String(s) if date_parts(s).is_some() => {
+            paths.extend(DERIVED_DATE_KEYS.iter().map(|k| format!("{}.{}", path, k)));
+        }
+        _ => (),
+    }
+}
+
+// A dotted path may split into keys in more than one way when the keys have dots in them, so each
+// split is tried until one of them leads to a value, as every path from collect_paths does
+fn lookup_in<'a>(mapping: &'a serde_yaml::Mapping, path: &str) -> Option<Cow<'a, MetadataValue>> {
+    if let Some(value) = mapping.get(&MetadataValue::from(path)) {
+        return Some(Cow::Borrowed(value));
+    }
+    path.match_indices('.').find_map(|(i, _)| {
+        let (key, rest) = (&path[..i], &path[i + 1..]);
+        match mapping.get(&MetadataValue::from(key))? {
+            MetadataValue::Mapping(m) => lookup_in(m, rest),
+            value => derived_value(value, rest).map(Cow::Owned),
+        }
+    })
+}
+
+// Dates become strings, just like YAML dates that serde_yaml does not understand either
+fn toml_to_yaml(value: toml::Value) -> MetadataValue {
+    match value {
+        toml::Value::String(s) => MetadataValue::String(s),
+        toml::Value::Integer(i) => MetadataValue::Number(i.into()),
+        toml::Value::Float(f) => MetadataValue::Number(f.into()),
+        toml::Value::Boolean(b) => MetadataValue::Bool(b),
+        toml::Value::Datetime(d) => MetadataValue::String(d.to_string()),
+        toml::Value::Array(a) => MetadataValue::Sequence(a.into_iter().map(toml_to_yaml).collect()),
+        toml::Value::Table(t) => MetadataValue::Mapping(t.into_iter()
+            .map(|(k, v)| (MetadataValue::String(k), toml_to_yaml(v)))
+            .collect()),
+    }
+}
+
+/// A metadata value as strings for looking pages up by it: each item of a list separately, and
+/// numbers and bools in their usual text form. Nulls and mappings have none.
+pub fn value_strings(value: &MetadataValue) -> Vec<String> {
+    match value {
+        MetadataValue::String(s) => vec![s.clone()],
+        MetadataValue::Number(n) => vec![n.to_string()],
+        MetadataValue::Bool(b) => vec![b.to_string()],
+        MetadataValue::Sequence(seq) => {
+            let mut strings = Vec::new();
+            for s in seq.iter().flat_map(value_strings) {
+                if !strings.contains(&s) {
+                    strings.push(s);
+                }
+            }
+            strings
+        }
+        MetadataValue::Null | MetadataValue::Mapping(_) => vec![],
+    }
+}
+
+/// The front matter of a page: a YAML mapping that is passed to templates as-is.
+#[derive(Debug)]
+pub struct Metadata {
+    data: serde_yaml::Mapping
+}
+
+pub type MetadataValue = serde_yaml::Value;
+
+impl Metadata {
+    // note: would be nice to have the as_bool() etc here too
+    pub fn get(&self, key: &str) -> Option<&MetadataValue> {
+        self.data.get(&serde_yaml::to_value(key).expect("string serialization failed??"))
+    }
+
+    // only for the keys that check_magic_keys has made sure are bools if they are there
+    pub(crate) fn get_bool_or_false(&self, key: &str) -> bool {
+        self.get(key)
+            .map(|x| x.as_bool().unwrap_or_else(|| panic!("metadata `{}' does not parse as a bool", key)))
+            .unwrap_or(false)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.data.iter().map(|(k, _)| k.as_str().expect("only string keys for now please"))
+    }
+
+    /// The value at a dotted path such as `series.name` through nested mappings, or a value
+    /// derived from a date such as `created_at.year`. A key that has dots in it is found too.
+    pub fn lookup(&self, path: &str) -> Option<Cow<'_, MetadataValue>> {
+        lookup_in(&self.data, path)
+    }
+
+    /// The names of the groups that a page with this metadata is in: each key, followed by the
+    /// dotted paths into it that `lookup` finds.
+    pub fn group_names(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        for (k, v) in &self.data {
+            let key = k.as_str().expect("only string keys for now please");
+            names.push(key.to_owned());
+            collect_paths(v, key, &mut names);
+        }
+        names
+    }
+
+    /// Whether each key of the filter has the given value here, as found by `lookup` and
+    /// `value_strings`.
+    pub fn matches(&self, filter: &BTreeMap<String, String>) -> bool {
+        filter.iter().all(|(key, wanted)| {
+            self.lookup(key).is_some_and(|v| value_strings(&v).contains(wanted))
+        })
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.data.contains_key(&serde_yaml::to_value(key).expect("string serialization failed??"))
+    }
+
+    /// The raw mapping, e.g., for serializing into a template context.
+    pub fn data(&self) -> &serde_yaml::Mapping {
+        &self.data
+    }
+
+    pub fn empty() -> Metadata {
+        Metadata { data: serde_yaml::Mapping::new() }
+    }
+
+    /// Parse a YAML mapping.
+    pub fn from_string(s: &str) -> Result<Metadata, serde_yaml::Error> {
+        let value = serde_yaml::from_str(s)?;
+        Ok(Metadata { data: value })
+    }
+
+    /// Parse a TOML table; the values are converted to their YAML equivalents so that templates
+    /// see no difference.
+    pub fn from_toml(s: &str) -> Result<Metadata, toml::de::Error> {
+        let table: toml::Table = toml::from_str(s)?;
+        let data = table.into_iter()
+            .map(|(k, v)| (MetadataValue::String(k), toml_to_yaml(v)))
+            .collect();
+        Ok(Metadata { data })
+    }
+
+    // The accessors above and those in Page assume that these hold, so this is checked as soon as
+    // a page is loaded
+    pub(crate) fn check_magic_keys(&self, draft_key: &str, has_default_template: bool)
+    -> Result<(), String> {
+        if let Some((k, _)) = self.data.iter().find(|(k, _)| !k.is_string()) {
+            return Err(format!("only string keys for now please, found {:?}", k));
+        }
+        match self.get(MAGIC_META_TEMPLATE) {
+            None if !has_default_template && !self.contains_key(MAGIC_META_FEED) =>
+                return Err(format!("must specify `{}'", MAGIC_META_TEMPLATE)),
+            Some(x) if !x.is_string() => return Err(format!("`{}' must be a string", MAGIC_META_TEMPLATE)),
+            _ => (),
+        }
+        if self.get(MAGIC_META_TITLE).is_some_and(|x| !x.is_string()) {
+            return Err(format!("`{}' must be a string", MAGIC_META_TITLE));
+        }
+        for key in &[MAGIC_META_URL_AS_IS, MAGIC_META_SITEMAP, draft_key] {
+            if self.get(key).is_some_and(|x| !x.is_bool()) {
+                return Err(format!("metadata `{}' does not parse as a bool", key));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_group_names_found(yaml: &str) {
+        let metadata = Metadata::from_string(yaml).unwrap();
+        for name in metadata.group_names() {
+            assert!(metadata.lookup(&name).is_some(), "{} not found in {}", name, yaml);
+        }
+    }
+
+    #[test]
+    fn group_names_are_found() {
+        assert_group_names_found("title: x\nseries: {name: a, part: 1}\ncreated_at: 2016-09-14");
+        assert_group_names_found("series: {published: {at: 2016-09-14T10:00:00}}");
+        assert_group_names_found("parent: {url: /a.html}");
+    }
+
+    #[test]
+    fn dotted_keys_are_found() {
+        assert_group_names_found("series: {\"v.2\": x}");
+        assert_group_names_found("\"a.b\": 1\na: {b: {c: 2}}");
+        let metadata = Metadata::from_string("series: {\"v.2\": x}\n\"a.b\": 1\na: {b: {c: 2}}").unwrap();
+        assert_eq!(metadata.lookup("series.v.2").unwrap().as_str(), Some("x"));
+        assert_eq!(metadata.lookup("a.b").unwrap().as_u64(), Some(1));
+        assert_eq!(metadata.lookup("a.b.c").unwrap().as_u64(), Some(2));
+    }
+
+    #[test]
+    fn dates_are_split() {
+        let metadata = Metadata::from_string("created_at: 2016-09-14\nseries: {at: 2017-01-02}").unwrap();
+        assert_eq!(metadata.lookup("created_at.year").unwrap().as_str(), Some("2016"));
+        assert_eq!(metadata.lookup("series.at.month").unwrap().as_str(), Some("01"));
+        assert!(metadata.lookup("created_at.day").is_none());
+    }
+}
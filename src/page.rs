@@ -0,0 +1,238 @@
+use std::path::{Path, PathBuf};
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::error::{Result, RotuliError, SourceLocation};
+use crate::frontmatter;
+use crate::metadata::{Metadata, MetadataValue, MAGIC_META_TEMPLATE, MAGIC_META_TITLE, MAGIC_META_URL_AS_IS};
+use crate::markup::MarkupRenderer;
+use crate::group::GroupReference;
+use crate::pagination::Pagination;
+use crate::taxonomy::Taxonomy;
+use crate::feed::Feed;
+
+/// An index to a page in a `Site`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PageReference(pub(crate) usize);
+
+/// A markup source file with its metadata and rendered content.
+pub struct Page {
+    path: PathBuf,
+    url: PathBuf,
+    title: String,
+    template: Option<String>,
+    pagination: Option<Pagination>,
+    taxonomy: Option<Taxonomy>,
+    feed: Option<Feed>,
+    pub(crate) metadata: Metadata,
+    content: String,
+    content_rendered: String,
+    summary_rendered: String,
+    document: Option<document_tree::Document>,
+    // filled after initial page construction
+    pub(crate) groups: Vec<GroupReference>,
+    pub(crate) references: Vec<(String, PageReference)>,
+    pub(crate) refs: BTreeMap<String, Vec<PageReference>>,
+}
+
+impl fmt::Debug for Page {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Page")
+            .field("url", &self.url)
+            .field("title", &self.title)
+            .finish()
+    }
+}
+
+// ("src/foo/some-page.markup", "src") -> "/foo/some-page"
+// ("src/foo/some-page.xml.markup", "src") -> "/foo/some-page.xml"
+// ("src/foo/index.markup", "src") -> "/foo"
+fn make_url(path: &Path, root: &Path, directory_index: &str) -> PathBuf {
+    let parent = path.parent().expect("a file must has a parent");
+    let formatted = if path.file_stem().is_some_and(|stem| stem == directory_index) {
+        // just strip the index part off
+        parent.to_path_buf()
+    } else {
+        // strip markup extension, append back to the directory
+        parent.join(path.file_stem().expect("this was supposed to be a file"))
+    };
+    let from_root = formatted.strip_prefix(root).expect("this was built from the root!!");
+
+    // perhaps should return a string because this isn't a real file in the fs anymore, but a path
+    // is more easily modified later if necessary
+    Path::new("/").join(from_root)
+}
+
+/// Site-wide settings that affect how each page is loaded.
+pub(crate) struct LoadOptions<'a> {
+    pub directory_index: &'a str,
+    pub draft_key: &'a str,
+    /// For pages that don't say which template they use.
+    pub default_template: Option<&'a str>,
+    /// Files of these extensions are pages only if they start with metadata, and plain files
+    /// otherwise.
+    pub metadata_required: &'a [&'a str],
+}
+
+impl Page {
+    // all have a parent directory and a file, from discover_source constraints
+    pub(crate) fn from_disk(path: &Path, root: &Path, renderer: &dyn MarkupRenderer,
+                            options: &LoadOptions) -> Result<Self> {
+        let data = std::fs::read_to_string(path)
+            .map_err(|e| RotuliError::Io { path: path.to_path_buf(), source: e })?;
+        let data = frontmatter::normalize(data);
+        let metadata_error = |location, message| RotuliError::Metadata {
+            path: path.to_path_buf(),
+            location,
+            message,
+        };
+        let frontmatter::FrontMatter { metadata, content, content_line } = frontmatter::parse(&data)
+            .map_err(|e| metadata_error(e.location, e.message))?;
+        metadata.check_magic_keys(options.draft_key, options.default_template.is_some())
+            .map_err(|message| metadata_error(None, message))?;
+        let feed = Feed::from_metadata(&metadata)
+            .map_err(|message| metadata_error(None, message))?;
+        let template = if feed.is_some() {
+            None
+        } else {
+            Some(metadata.get(MAGIC_META_TEMPLATE)
+                .map(|t| t.as_str().expect("checked to be a string"))
+                .or(options.default_template)
+                .expect("checked to exist")
+                .to_owned())
+        };
+        let pagination = Pagination::from_metadata(&metadata)
+            .map_err(|message| metadata_error(None, message))?;
+        let taxonomy = Taxonomy::from_metadata(&metadata)
+            .map_err(|message| metadata_error(None, message))?;
+        let url = make_url(path, root, options.directory_index);
+        // markup errors are relative to the content, which starts after the metadata
+        let render_result = renderer.render(content).map_err(|e| RotuliError::Markup {
+            path: path.to_path_buf(),
+            location: e.location.map(|l| SourceLocation::new(l.line + content_line, l.column)),
+            message: e.message,
+        })?;
+
+        Ok(Page {
+            path: Path::new("/").join(path.strip_prefix(root).expect("glob betrayed us")),
+            url,
+            title: render_result.title,
+            template,
+            pagination,
+            taxonomy,
+            feed,
+            metadata,
+            content: content.to_string(),
+            content_rendered: render_result.body,
+            summary_rendered: render_result.summary,
+            document: render_result.document,
+            groups: vec![],
+            references: vec![],
+            refs: BTreeMap::new(),
+        })
+    }
+
+    /// Source file path relative to the site directory, starting with a slash.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // append a slash for things that look like a directory, because those are rendered into
+    // directories for nice things (e.g., avoid linking to the html extension, emphasizing just the
+    // document structure)
+    pub fn display_url(&self) -> String {
+        let as_is = self.metadata.get_bool_or_false(MAGIC_META_URL_AS_IS);
+
+        let as_string = self.url.to_str().expect("only UTF-8 files please").to_owned();
+        // why both though?
+        if as_string == "/" || as_is {
+            as_string
+        } else {
+            as_string + "/"
+        }
+    }
+
+    /// Where the page gets rendered to, relative to the output directory.
+    pub fn output_path(&self) -> PathBuf {
+        let as_is = self.metadata.get_bool_or_false(MAGIC_META_URL_AS_IS);
+
+        let relative = self.url.strip_prefix("/").expect("`/' was added before but now it's gone?");
+        if as_is {
+            relative.to_path_buf()
+        } else {
+            relative.join("index.html")
+        }
+    }
+
+    /// The template from metadata, or the site default; feeds have none.
+    pub fn template_name(&self) -> Option<&str> {
+        self.template.as_deref()
+    }
+
+    /// How this page is split to several outputs, if it is.
+    pub fn pagination(&self) -> Option<&Pagination> {
+        self.pagination.as_ref()
+    }
+
+    /// Which group this page is rendered for once per value, if any.
+    pub fn taxonomy(&self) -> Option<&Taxonomy> {
+        self.taxonomy.as_ref()
+    }
+
+    /// The feed that this page is rendered as instead of a template, if any.
+    pub fn feed(&self) -> Option<&Feed> {
+        self.feed.as_ref()
+    }
+
+    /// The title from metadata if one is given, or the one from the markup.
+    pub fn title(&self) -> &str {
+        self.metadata.get(MAGIC_META_TITLE).map(|x| x.as_str().expect("title must be a string"))
+            .unwrap_or(&self.title)
+    }
+
+    pub fn get_meta(&self, key: &str) -> Option<&MetadataValue> {
+        self.metadata.get(key)
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    pub fn url(&self) -> &Path {
+        &self.url
+    }
+
+    /// The markup source, without the metadata header.
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+
+    pub fn content_rendered(&self) -> &str {
+        &self.content_rendered
+    }
+
+    pub fn summary_rendered(&self) -> &str {
+        &self.summary_rendered
+    }
+
+    /// The parsed markup, if the renderer provides one.
+    pub fn document(&self) -> Option<&document_tree::Document> {
+        self.document.as_ref()
+    }
+
+    /// All groups this page belongs to, in metadata order.
+    pub fn groups(&self) -> &[GroupReference] {
+        &self.groups
+    }
+
+    /// The pages that this one refers to in its metadata, with the dotted metadata paths of the
+    /// references.
+    pub fn references(&self) -> &[(String, PageReference)] {
+        &self.references
+    }
+
+    /// The pages that refer to this one, by the metadata path of the reference.
+    pub fn refs(&self) -> &BTreeMap<String, Vec<PageReference>> {
+        &self.refs
+    }
+}
@@ -0,0 +1,104 @@
+use std::cmp::Ordering;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+use crate::metadata::{value_strings, Metadata, MetadataValue, MAGIC_META_PAGINATE, MAGIC_META_URL_AS_IS};
+
+/// Splits a listing page into several outputs of a fixed number of pages of a group each, as in
+///
+/// ```yaml
+/// paginate: { group: category, value: animals, per_page: 10, sort_by: created_at }
+/// ```
+///
+/// The numbering starts from the oldest items so that a page keeps its number and url as new
+/// items are added; the most recent items are on the page with the largest number. The first page
+/// has the url of the source page, and the page number n is in the subdirectory n.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Pagination {
+    /// The pages of this group are listed.
+    pub group: String,
+    /// If given, only the pages whose group value is this, or is a list that contains this; see
+    /// `value_strings`.
+    #[serde(default)]
+    pub value: Option<String>,
+    pub per_page: usize,
+    /// Metadata key or dotted path to sort by, oldest first; by default, the order of the group.
+    #[serde(default)]
+    pub sort_by: Option<String>,
+}
+
+impl Pagination {
+    pub(crate) fn from_metadata(metadata: &Metadata) -> Result<Option<Self>, String> {
+        let value = match metadata.get(MAGIC_META_PAGINATE) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let pagination = serde_yaml::from_value::<Pagination>(value.clone())
+            .map_err(|e| format!("bad `{}': {}", MAGIC_META_PAGINATE, e))?;
+        if pagination.per_page == 0 {
+            return Err(format!("`{}' needs at least one page per page", MAGIC_META_PAGINATE));
+        }
+        if metadata.get_bool_or_false(MAGIC_META_URL_AS_IS) {
+            return Err(format!("`{}' cannot be used with `{}', the pages need directories",
+                MAGIC_META_PAGINATE, MAGIC_META_URL_AS_IS));
+        }
+        Ok(Some(pagination))
+    }
+
+    /// Whether a page with this metadata is listed.
+    pub fn includes(&self, metadata: &Metadata) -> bool {
+        let wanted = match &self.value {
+            Some(wanted) => wanted,
+            None => return metadata.lookup(&self.group).is_some(),
+        };
+        metadata.lookup(&self.group).is_some_and(|v| value_strings(&v).contains(wanted))
+    }
+
+    /// Oldest first, by the sort key; pages without it come first.
+    pub(crate) fn compare(&self, a: &Metadata, b: &Metadata) -> Ordering {
+        let key = match &self.sort_by {
+            Some(key) => key,
+            None => return Ordering::Equal,
+        };
+        match (a.lookup(key), b.lookup(key)) {
+            (Some(a), Some(b)) => compare_values(&a, &b),
+            (a, b) => a.is_some().cmp(&b.is_some()),
+        }
+    }
+}
+
+// numbers as numbers, the rest as their YAML text, which works for dates too
+fn compare_values(a: &MetadataValue, b: &MetadataValue) -> Ordering {
+    match (a.as_f64(), b.as_f64()) {
+        (Some(a), Some(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+        _ => value_text(a).cmp(&value_text(b)),
+    }
+}
+
+fn value_text(value: &MetadataValue) -> String {
+    match value.as_str() {
+        Some(s) => s.to_owned(),
+        None => serde_yaml::to_string(value).unwrap_or_default(),
+    }
+}
+
+/// The url of page `number` (from 1) of a paginated page at `url`, which ends in a slash.
+pub(crate) fn page_url(url: &str, number: usize) -> String {
+    if number == 1 {
+        url.to_owned()
+    } else {
+        format!("{}{}/", url, number)
+    }
+}
+
+/// Like `page_url`, but for the output file relative to the output directory.
+pub(crate) fn page_output_path(output_path: PathBuf, number: usize) -> PathBuf {
+    if number == 1 {
+        output_path
+    } else {
+        let dir = output_path.parent().expect("paginated pages are in directories");
+        dir.join(number.to_string()).join("index.html")
+    }
+}
@@ -0,0 +1,154 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// How plain files get from the source directory to the output directory.
+///
+/// The ways other than copying save the space and the time of big files such as photos, but they
+/// do not work everywhere: hard links do not cross filesystems and only some filesystems can
+/// share the data of files. When a file cannot be linked, it is copied instead.
+#[derive(Debug, Copy, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkMode {
+    #[default]
+    Copy,
+    Hardlink,
+    /// A copy that shares the data with the source until either is modified, on filesystems such
+    /// as Btrfs, XFS and APFS.
+    Reflink,
+    /// A symbolic link to the absolute path of the source; the output directory is then not of
+    /// much use without the source directory.
+    Symlink,
+}
+
+impl LinkMode {
+    pub fn all() -> &'static [LinkMode] {
+        &[LinkMode::Copy, LinkMode::Hardlink, LinkMode::Reflink, LinkMode::Symlink]
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            LinkMode::Copy => "copy",
+            LinkMode::Hardlink => "hardlink",
+            LinkMode::Reflink => "reflink",
+            LinkMode::Symlink => "symlink",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct LinkModeParseError;
+
+impl fmt::Display for LinkModeParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown link mode, please use one of: [copy, hardlink, reflink, symlink]")
+    }
+}
+
+impl std::error::Error for LinkModeParseError {}
+
+impl FromStr for LinkMode {
+    type Err = LinkModeParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        LinkMode::all().iter().find(|m| m.as_str() == s).cloned().ok_or(LinkModeParseError)
+    }
+}
+
+impl fmt::Display for LinkMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[cfg(unix)]
+fn same_file(a: &fs::Metadata, b: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    a.dev() == b.dev() && a.ino() == b.ino()
+}
+
+#[cfg(not(unix))]
+fn same_file(_a: &fs::Metadata, _b: &fs::Metadata) -> bool {
+    false
+}
+
+/// Whether a file has other names, such as a hard link made by `place`.
+#[cfg(unix)]
+pub(crate) fn is_linked(meta: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    meta.nlink() > 1
+}
+
+#[cfg(not(unix))]
+pub(crate) fn is_linked(_meta: &fs::Metadata) -> bool {
+    false
+}
+
+/// Whether `output` already is what placing `source` there with `mode` would make, so that it
+/// can be left alone even without a record of an earlier build. Copies are compared by size and
+/// modification time like rsync does, so `place` gives them the time of the source.
+pub(crate) fn is_in_place(source: &Path, output: &Path, mode: LinkMode) -> bool {
+    let (source_meta, output_meta) = match (fs::metadata(source), fs::symlink_metadata(output)) {
+        (Ok(s), Ok(o)) => (s, o),
+        _ => return false,
+    };
+    match mode {
+        LinkMode::Hardlink => same_file(&source_meta, &output_meta),
+        LinkMode::Symlink => output_meta.file_type().is_symlink()
+            && fs::read_link(output).ok() == fs::canonicalize(source).ok(),
+        LinkMode::Copy | LinkMode::Reflink => output_meta.is_file()
+            && !same_file(&source_meta, &output_meta)
+            && source_meta.len() == output_meta.len()
+            && source_meta.modified().ok().is_some_and(|t| output_meta.modified().ok() == Some(t)),
+    }
+}
+
+// copies get the time of their source for is_in_place
+fn set_modified_as(source: &Path, output: &Path) -> io::Result<()> {
+    let modified = fs::metadata(source)?.modified()?;
+    fs::File::options().write(true).open(output)?.set_modified(modified)
+}
+
+fn copy(source: &Path, output: &Path) -> io::Result<()> {
+    fs::copy(source, output)?;
+    set_modified_as(source, output)
+}
+
+/// Put `source` at `output` with `mode`, replacing what was there, and falling back to a copy if
+/// that does not work. Gives the mode that was used.
+pub(crate) fn place(source: &Path, output: &Path, mode: LinkMode) -> io::Result<LinkMode> {
+    // writing into a link from an earlier build would write into its source
+    match fs::remove_file(output) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => (),
+    }
+    let linked = match mode {
+        LinkMode::Copy => return copy(source, output).map(|()| LinkMode::Copy),
+        LinkMode::Hardlink => fs::hard_link(source, output),
+        LinkMode::Reflink => reflink_copy::reflink(source, output)
+            .and_then(|()| set_modified_as(source, output)),
+        LinkMode::Symlink => symlink(source, output),
+    };
+    match linked {
+        Ok(()) => Ok(mode),
+        Err(_) => {
+            // a half-made link or clone is in the way of the copy
+            let _ = fs::remove_file(output);
+            copy(source, output).map(|()| LinkMode::Copy)
+        }
+    }
+}
+
+#[cfg(unix)]
+fn symlink(source: &Path, output: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(fs::canonicalize(source)?, output)
+}
+
+#[cfg(windows)]
+fn symlink(source: &Path, output: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(fs::canonicalize(source)?, output)
+}
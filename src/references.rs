@@ -0,0 +1,90 @@
+// Page references in metadata: a mapping of just `page: /some/url/` anywhere in the metadata,
+// e.g., `translations: { en: { page: /frontpage/ } }`. In templates the reference becomes the
+// context of the referred page, and that page gets the referring one in its `refs`, keyed by the
+// dotted metadata path of the reference ("translations.en").
+//
+// (YAML tags such as "!pageref /frontpage/" would be nicer, but serde_yaml drops them.)
+
+use std::collections::HashMap;
+
+use crate::metadata::{Metadata, MetadataValue};
+use crate::page::PageReference;
+
+const REFERENCE_KEY: &str = "page";
+
+pub(crate) fn is_reference(value: &MetadataValue) -> bool {
+    as_reference(value).is_some()
+}
+
+// the url if this value is a page reference
+fn as_reference(value: &MetadataValue) -> Option<&str> {
+    match value {
+        MetadataValue::Mapping(m) if m.len() == 1 => m.get(&MetadataValue::from(REFERENCE_KEY))?.as_str(),
+        _ => None,
+    }
+}
+
+fn join_key(path: &str, key: &MetadataValue) -> String {
+    let key = key.as_str().map(|k| k.to_owned())
+        .unwrap_or_else(|| serde_yaml::to_string(key).unwrap_or_default().trim().to_owned());
+    if path.is_empty() {
+        key
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+fn find_in(value: &MetadataValue, path: String, found: &mut Vec<(String, String)>) {
+    if let Some(url) = as_reference(value) {
+        found.push((path, url.to_owned()));
+        return;
+    }
+    match value {
+        MetadataValue::Mapping(m) => {
+            for (k, v) in m {
+                find_in(v, join_key(&path, k), found);
+            }
+        }
+        // the items of a list share the path of the list
+        MetadataValue::Sequence(seq) => {
+            for v in seq {
+                find_in(v, path.clone(), found);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// All page references of the metadata as (dotted metadata path, url).
+pub(crate) fn find(metadata: &Metadata) -> Vec<(String, String)> {
+    let mut found = Vec::new();
+    for (k, v) in metadata.data() {
+        find_in(v, join_key("", k), &mut found);
+    }
+    found
+}
+
+/// The page of a reference url; the trailing slash of directory urls is optional.
+pub(crate) fn lookup(url: &str, pages_by_url: &HashMap<String, PageReference>) -> Option<PageReference> {
+    pages_by_url.get(url)
+        .or_else(|| if url.ends_with('/') { None } else { pages_by_url.get(&format!("{}/", url)) })
+        .copied()
+}
+
+/// A copy of the value with each page reference replaced by what `resolve` gives for its url;
+/// dangling ones are left as they are.
+pub(crate) fn replace(value: &MetadataValue, resolve: &dyn Fn(&str) -> Option<MetadataValue>)
+-> MetadataValue {
+    if let Some(resolved) = as_reference(value).and_then(resolve) {
+        return resolved;
+    }
+    match value {
+        MetadataValue::Mapping(m) => MetadataValue::Mapping(m.iter()
+            .map(|(k, v)| (k.clone(), replace(v, resolve)))
+            .collect()),
+        MetadataValue::Sequence(seq) => MetadataValue::Sequence(seq.iter()
+            .map(|v| replace(v, resolve))
+            .collect()),
+        _ => value.clone(),
+    }
+}
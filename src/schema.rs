@@ -0,0 +1,285 @@
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::error::{Result, RotuliError};
+use crate::feed::parse_date;
+use crate::frontmatter::yaml_error;
+use crate::metadata::{value_strings, Metadata, MetadataValue};
+use crate::page::{Page, PageReference};
+use crate::references;
+
+/// Where the schema is looked for in the source directory; as a hidden file it is not copied.
+pub(crate) const SCHEMA_FILE: &str = ".rotuli-schema.yaml";
+
+/// What the metadata of pages must look like, by group and by template:
+///
+/// ```yaml
+/// groups:
+///   blog:
+///     required: [created_at, language]
+///     recommended: [category]
+///     keys:
+///       created_at: { type: date }
+///       category: { type: strings }
+///       language: { type: string, values: [en, fi] }
+/// templates:
+///   category-en.html:
+///     required: [category_name]
+/// checks:
+///   - unique: { group: translation_key, keys: [translation_key, language] }
+/// ```
+///
+/// A page is checked against the rules of each group it is in and those of its template, and
+/// the checks are about all pages together. The keys can be dotted paths as in groups.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Schema {
+    #[serde(default)]
+    groups: BTreeMap<String, Rules>,
+    #[serde(default)]
+    templates: BTreeMap<String, Rules>,
+    #[serde(default)]
+    checks: Vec<Check>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Rules {
+    /// These keys must exist.
+    #[serde(default)]
+    required: Vec<String>,
+    /// These keys should exist; pages without them only get a note.
+    #[serde(default)]
+    recommended: Vec<String>,
+    /// These keys must be like this if they exist.
+    #[serde(default)]
+    keys: BTreeMap<String, KeyRule>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct KeyRule {
+    #[serde(default, rename = "type")]
+    value_type: Option<ValueType>,
+    /// The value, or each item of a list, must be one of these.
+    #[serde(default)]
+    values: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ValueType {
+    String,
+    /// A string such as "2016-09-14" or "2016-09-14T12:00:00+03:00".
+    Date,
+    /// A list of strings.
+    Strings,
+    Bool,
+}
+
+/// What must hold between the pages of the site.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub(crate) enum Check {
+    /// No two pages of the group have the same values of all of these keys; pages that lack some
+    /// of them are not compared.
+    Unique { group: String, keys: Vec<String> },
+    /// Each value of `key` in the group is a value of `page_key` in some page that also has the
+    /// same values of the `same` keys, e.g., each category of a post has a page in its language.
+    HasPage {
+        group: String,
+        key: String,
+        page_key: String,
+        #[serde(default)]
+        same: Vec<String>,
+    },
+    /// Each value of `key` is the url of a page that has that value too, e.g., a translation key
+    /// is the url of one of the translations.
+    OwnUrl { key: String },
+}
+
+// "`a'", "`a' and `b'", "`a', `b' and `c'"
+fn quoted(keys: &[String]) -> String {
+    let quoted = keys.iter().map(|k| format!("`{}'", k)).collect::<Vec<_>>();
+    match quoted.split_last() {
+        Some((last, rest)) if !rest.is_empty() => format!("{} and {}", rest.join(", "), last),
+        _ => quoted.join(""),
+    }
+}
+
+// the values of each key, or none if a key is missing
+fn values_of(page: &Page, keys: &[String]) -> Option<Vec<Vec<String>>> {
+    keys.iter().map(|k| page.metadata.lookup(k).map(|v| value_strings(&v))).collect()
+}
+
+fn in_group<'a>(pages: &'a [Page], group: &'a str) -> impl Iterator<Item = (PageReference, &'a Page)> {
+    pages.iter().enumerate()
+        .filter(move |(_, p)| p.metadata.lookup(group).is_some())
+        .map(|(i, p)| (PageReference(i), p))
+}
+
+impl Check {
+    fn run(&self, pages: &[Page], pages_by_url: &HashMap<String, PageReference>,
+            problems: &mut Vec<(PageReference, String)>) {
+        match self {
+            Check::Unique { group, keys } => {
+                let mut seen = HashMap::new();
+                for (r, p) in in_group(pages, group) {
+                    let values = match values_of(p, keys) {
+                        Some(values) => values,
+                        None => continue,
+                    };
+                    match seen.entry(values) {
+                        Entry::Vacant(e) => { e.insert(p); }
+                        Entry::Occupied(e) => problems.push((r, format!("{} of this page are the same as in {}",
+                            quoted(keys), e.get().display_url()))),
+                    }
+                }
+            }
+            Check::HasPage { group, key, page_key, same } => {
+                let mut by_value = HashMap::<String, Vec<&Page>>::new();
+                for p in pages {
+                    for value in p.metadata.lookup(page_key).map_or_else(Vec::new, |v| value_strings(&v)) {
+                        by_value.entry(value).or_default().push(p);
+                    }
+                }
+                let same_values = |p: &Page| same.iter()
+                    .map(|k| p.metadata.lookup(k).map(|v| value_strings(&v)))
+                    .collect::<Vec<_>>();
+                for (r, p) in in_group(pages, group) {
+                    let wanted = same_values(p);
+                    for value in p.metadata.lookup(key).map_or_else(Vec::new, |v| value_strings(&v)) {
+                        let found = by_value.get(&value)
+                            .is_some_and(|candidates| candidates.iter().any(|&c| same_values(c) == wanted));
+                        if !found {
+                            let also = if same.is_empty() { String::new() } else { format!(" and the same {}", quoted(same)) };
+                            problems.push((r, format!("`{}' {:?} has no page: none has `{}' {:?}{}",
+                                key, value, page_key, value, also)));
+                        }
+                    }
+                }
+            }
+            Check::OwnUrl { key } => {
+                // each value is reported once, at the first page that has it
+                let mut first = BTreeMap::new();
+                for (r, p) in in_group(pages, key) {
+                    for value in p.metadata.lookup(key).map_or_else(Vec::new, |v| value_strings(&v)) {
+                        first.entry(value).or_insert(r);
+                    }
+                }
+                for (value, r) in first {
+                    match pages_by_url.get(&value).map(|owner| &pages[owner.0]) {
+                        Some(owner) if owner.metadata.lookup(key).is_some_and(|v| value_strings(&v).contains(&value)) => (),
+                        Some(_) => problems.push((r, format!("`{}' {:?} is the url of a page with another `{}'",
+                            key, value, key))),
+                        None => {
+                            let hint = references::lookup(&value, pages_by_url)
+                                .map_or_else(String::new, |o| format!("; did you mean {}?", pages[o.0].display_url()));
+                            problems.push((r, format!("`{}' {:?} is not the url of a page{}", key, value, hint)));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl ValueType {
+    fn matches(self, value: &MetadataValue) -> bool {
+        match self {
+            ValueType::String => value.is_string(),
+            ValueType::Date => value.as_str().and_then(parse_date).is_some(),
+            ValueType::Strings => value.as_sequence().is_some_and(|seq| seq.iter().all(|v| v.is_string())),
+            ValueType::Bool => value.is_bool(),
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            ValueType::String => "a string",
+            ValueType::Date => "a date",
+            ValueType::Strings => "a list of strings",
+            ValueType::Bool => "a bool",
+        }
+    }
+}
+
+impl Schema {
+    /// Read the schema file of a source directory, if there is one.
+    pub(crate) fn load(directory: &Path) -> Result<Option<Schema>> {
+        let path = directory.join(SCHEMA_FILE);
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(RotuliError::Io { path, source: e }),
+        };
+        serde_yaml::from_str(&text).map(Some).map_err(|e| {
+            let e = yaml_error(e, 0);
+            RotuliError::Metadata { path, location: e.location, message: e.message }
+        })
+    }
+
+    // the rules for a page with these groups and template, with what they are for
+    fn rules_for<'a, I>(&'a self, groups: I, template: Option<&'a str>) -> impl Iterator<Item = (String, &'a Rules)>
+    where I: IntoIterator<Item = &'a str> {
+        groups.into_iter()
+            .filter_map(move |g| self.groups.get(g).map(|r| (format!("group `{}'", g), r)))
+            .chain(template.and_then(|t| self.templates.get(t).map(|r| (format!("template `{}'", t), r))))
+    }
+
+    /// The ways in which the metadata of a page with these groups and template breaks the
+    /// schema, each naming the rule it breaks.
+    pub(crate) fn check<'a, I>(&'a self, metadata: &Metadata, groups: I, template: Option<&'a str>) -> Vec<String>
+    where I: IntoIterator<Item = &'a str> {
+        let mut problems = Vec::new();
+        for (owner, rules) in self.rules_for(groups, template) {
+            for key in rules.required.iter().filter(|k| metadata.lookup(k).is_none()) {
+                problems.push(format!("`{}' is required for the {}", key, owner));
+            }
+            for (key, rule) in &rules.keys {
+                let value = match metadata.lookup(key) {
+                    Some(value) => value,
+                    None => continue,
+                };
+                if let Some(value_type) = rule.value_type.filter(|t| !t.matches(&value)) {
+                    problems.push(format!("`{}' must be {} for the {}", key, value_type.description(), owner));
+                }
+                if let Some(allowed) = &rule.values {
+                    for bad in value_strings(&value).into_iter().filter(|v| !allowed.contains(v)) {
+                        problems.push(format!("`{}' cannot be \"{}\" for the {}; it can be one of: {}",
+                            key, bad, owner, allowed.join(", ")));
+                    }
+                }
+            }
+        }
+        problems
+    }
+
+    /// The recommended keys that the metadata of a page with these groups and template lacks,
+    /// as notes that are not errors.
+    pub(crate) fn notes<'a, I>(&'a self, metadata: &Metadata, groups: I, template: Option<&'a str>) -> Vec<String>
+    where I: IntoIterator<Item = &'a str> {
+        let mut notes = Vec::new();
+        for (owner, rules) in self.rules_for(groups, template) {
+            for key in rules.recommended.iter().filter(|k| metadata.lookup(k).is_none()) {
+                notes.push(format!("`{}' is recommended for the {}", key, owner));
+            }
+        }
+        notes
+    }
+
+    /// The ways in which the pages together break the checks of the schema, at the pages that
+    /// break them, in the order of the pages.
+    pub(crate) fn check_site(&self, pages: &[Page], pages_by_url: &HashMap<String, PageReference>)
+    -> Vec<(PageReference, String)> {
+        let mut problems = Vec::new();
+        for check in &self.checks {
+            check.run(pages, pages_by_url, &mut problems);
+        }
+        problems.sort_by_key(|(r, _)| r.0);
+        problems
+    }
+}
@@ -0,0 +1,944 @@
+use std::path::{Path, PathBuf};
+use std::borrow::Cow;
+use std::collections::{BTreeSet, BTreeMap, HashMap};
+use std::sync::Arc;
+
+use glob::{glob_with, MatchOptions};
+use tera::{Tera, Context};
+use serde::Serialize;
+use rayon::prelude::*;
+
+use crate::error::{Result, RotuliError};
+use crate::frontmatter;
+use crate::markup::{MarkupLanguage, MarkupRenderer};
+use crate::page::{LoadOptions, Page, PageReference};
+use crate::group::{Group, GroupReference};
+use crate::metadata::{value_strings, MetadataValue, MAGIC_META_SITEMAP};
+use crate::pagination::{self, Pagination};
+use crate::plain::{self, LinkMode};
+use crate::references;
+use crate::schema::Schema;
+use crate::state::{fingerprint, BuildState, Dependencies};
+use crate::taxonomy::{self, Taxonomy};
+use crate::feed::{self, Feed, Timestamp};
+use crate::sitemap::{self, Sitemap};
+use crate::templates::{tera_error_message, template_dependencies};
+
+type Renderers = BTreeMap<String, Arc<dyn MarkupRenderer>>;
+
+fn renderer_for<'a>(path: &Path, renderers: &'a Renderers) -> Option<&'a dyn MarkupRenderer> {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .and_then(|e| renderers.get(e))
+        .map(|r| &**r)
+}
+
+// markup files are recognized by the extensions of the renderers, and by their metadata for
+// the extensions that need it
+fn discover_source(source: &Path, renderers: &Renderers, metadata_required: &[&str])
+-> (Vec<PathBuf>, Vec<PathBuf>) {
+    let pathname = source.to_str().expect("only UTF-8 directories please").to_owned();
+    let opts = MatchOptions {
+        require_literal_leading_dot: true,
+        ..Default::default()
+    };
+    let paths = glob_with(&(pathname + "/**/*"), opts).expect("invalid search pattern");
+    // silently ignore Err items, unreadable files are skipped on purpose
+    // (FIXME: verbose mode to print them)
+    let pathbufs: Vec<_> = paths.filter_map(|x| x.ok()).filter(|x| x.is_file()).collect();
+    let has_metadata = |x: &Path| std::fs::read_to_string(x)
+        .is_ok_and(|data| frontmatter::has_metadata(&frontmatter::normalize(data)));
+    let is_markup = |x: &Path| renderer_for(x, renderers).is_some()
+        && (!x.extension().and_then(|e| e.to_str()).is_some_and(|e| metadata_required.contains(&e))
+            || has_metadata(x));
+
+    pathbufs.into_iter().partition(|x| is_markup(x))
+}
+
+// for fingerprinting contexts
+fn yaml_string<T: Serialize>(value: &T) -> String {
+    serde_yaml::to_string(value).expect("context serialization failed??")
+}
+
+fn create_parent_dir(file: &Path) -> Result<()> {
+    let dir = file.parent().expect("tried to write to the root, huh?");
+    std::fs::create_dir_all(dir).map_err(|e| RotuliError::Io { path: dir.to_path_buf(), source: e })
+}
+
+fn write_output(file: &Path, contents: &[u8]) -> Result<()> {
+    create_parent_dir(file)?;
+    // a plain file linked there by an earlier build would get written through to its source
+    if std::fs::symlink_metadata(file).is_ok_and(|m| !m.is_file() || plain::is_linked(&m)) {
+        std::fs::remove_file(file).map_err(|e| RotuliError::Io { path: file.to_path_buf(), source: e })?;
+    }
+    std::fs::write(file, contents).map_err(|e| RotuliError::Io { path: file.to_path_buf(), source: e })
+}
+
+/// A page rendered through its template, waiting to be written.
+#[derive(Debug)]
+pub struct RenderedPage {
+    /// Relative to the output directory.
+    pub output_path: PathBuf,
+    pub text: String,
+    dependencies: Dependencies,
+}
+
+impl RenderedPage {
+    /// Write the page to the output directory of the build and record it there.
+    pub fn write(&self, state: &mut BuildState) -> Result<()> {
+        write_output(&state.output_dir().join(&self.output_path), self.text.as_bytes())?;
+        state.record(self.output_path.clone(), self.dependencies.clone());
+        Ok(())
+    }
+}
+
+/// Loads a `Site`; the defaults match those of the command line tool.
+#[derive(Clone)]
+pub struct SiteBuilder {
+    directory: PathBuf,
+    markup_languages: Vec<MarkupLanguage>,
+    custom_renderers: Vec<(String, Arc<dyn MarkupRenderer>)>,
+    directory_index: String,
+    draft_key: String,
+    default_template: Option<String>,
+}
+
+impl SiteBuilder {
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Self {
+        SiteBuilder {
+            directory: directory.into(),
+            markup_languages: MarkupLanguage::all().to_vec(),
+            custom_renderers: vec![],
+            directory_index: "index".to_owned(),
+            draft_key: "draft".to_owned(),
+            default_template: None,
+        }
+    }
+
+    /// Treat only files of these languages as pages instead of all known languages; the rest are
+    /// plain files.
+    pub fn markup_languages(mut self, markup_languages: &[MarkupLanguage]) -> Self {
+        self.markup_languages = markup_languages.to_vec();
+        self
+    }
+
+    /// Treat files with this extension as pages rendered with the given renderer. This overrides a
+    /// built-in language of the same extension.
+    pub fn markup_renderer<R: MarkupRenderer + 'static>(mut self, extension: &str, renderer: R) -> Self {
+        self.custom_renderers.push((extension.to_owned(), Arc::new(renderer)));
+        self
+    }
+
+    /// File name (sans extension) of sources that render to their directory's url, in any of the
+    /// markup languages.
+    pub fn directory_index(mut self, directory_index: &str) -> Self {
+        self.directory_index = directory_index.to_owned();
+        self
+    }
+
+    /// Pages with this metadata key set to true are skipped.
+    pub fn draft_key(mut self, draft_key: &str) -> Self {
+        self.draft_key = draft_key.to_owned();
+        self
+    }
+
+    /// Template for pages that do not specify one, e.g., those with no metadata at all. Without
+    /// this, every page must have a template.
+    pub fn default_template(mut self, template: &str) -> Self {
+        self.default_template = Some(template.to_owned());
+        self
+    }
+
+    /// Load the site, failing on the first broken page.
+    pub fn build(self) -> Result<Site> {
+        let (site, errors) = self.build_keep_going();
+        match errors.into_iter().next() {
+            Some(e) => Err(e),
+            None => Ok(site),
+        }
+    }
+
+    /// Load every page that can be loaded; the broken ones are left out of the site and their
+    /// errors are returned along with it.
+    pub fn build_keep_going(self) -> (Site, Vec<RotuliError>) {
+        let mut renderers = Renderers::new();
+        for &ml in &self.markup_languages {
+            renderers.insert(ml.as_str().to_owned(), Arc::new(ml));
+        }
+        // plain html files such as site verification files stay plain unless they have metadata
+        let passthrough = MarkupLanguage::Passthrough.as_str();
+        let metadata_required = if self.markup_languages.contains(&MarkupLanguage::Passthrough)
+                && !self.custom_renderers.iter().any(|(ext, _)| ext == passthrough) {
+            vec![passthrough]
+        } else {
+            vec![]
+        };
+        renderers.extend(self.custom_renderers);
+        let options = LoadOptions {
+            directory_index: &self.directory_index,
+            draft_key: &self.draft_key,
+            default_template: self.default_template.as_deref(),
+            metadata_required: &metadata_required,
+        };
+        Site::new(self.directory, &renderers, &options)
+    }
+}
+
+/// A source directory loaded in memory: pages, groups and the plain files to copy as-is.
+///
+/// Groups by name and pages by url and by source path are indexed when loading, so that looking
+/// them up does not depend on the size of the site.
+pub struct Site {
+    directory: PathBuf,
+    pages: Vec<Page>,
+    groups: Vec<Group>,
+    plain_files: Vec<PathBuf>,
+    draft_key: String,
+    groups_by_name: HashMap<String, GroupReference>,
+    pages_by_url: HashMap<String, PageReference>,
+    pages_by_path: HashMap<PathBuf, PageReference>,
+    notes: Vec<(PathBuf, String)>,
+}
+
+impl Site {
+    pub fn builder<P: Into<PathBuf>>(directory: P) -> SiteBuilder {
+        SiteBuilder::new(directory)
+    }
+
+    fn new(dir: PathBuf, renderers: &Renderers, options: &LoadOptions) -> (Self, Vec<RotuliError>) {
+        // Load source data as pages with just metadata properly initialized
+        let (src_markup, src_plain_files) = discover_source(&dir, renderers, options.metadata_required);
+        let page_ok = |p: &Page| !p.metadata.get_bool_or_false(options.draft_key);
+
+        // the markup rendering is the slow part, so do that in parallel; the order stays
+        let loaded = src_markup.par_iter()
+            .map(|path| {
+                let renderer = renderer_for(path, renderers).expect("discovered as markup");
+                Page::from_disk(path, &dir, renderer, options)
+            })
+            .collect::<Vec<_>>();
+
+        let mut errors = Vec::new();
+        let pages = loaded.into_iter()
+            .filter_map(|p| p.map_err(|e| errors.push(e)).ok())
+            .filter(page_ok)
+            .collect::<Vec<_>>();
+
+        // e.g., foo.rst and foo/index.rst, or foo.rst and foo.md; neither of them wins
+        let mut sources_by_url = BTreeMap::<String, Vec<&Path>>::new();
+        for p in &pages {
+            sources_by_url.entry(p.display_url()).or_default().push(p.path());
+        }
+        let duplicate_urls = sources_by_url.into_iter()
+            .filter(|(_, paths)| paths.len() > 1)
+            .map(|(url, paths)| {
+                errors.push(RotuliError::DuplicateUrl {
+                    paths: paths.iter().map(|p| dir.join(p.strip_prefix("/").expect("paths are absolute"))).collect(),
+                    url: url.clone(),
+                });
+                url
+            })
+            .collect::<BTreeSet<_>>();
+        let pages = pages.into_iter()
+            .filter(|p| !duplicate_urls.contains(&p.display_url()))
+            .collect::<Vec<_>>();
+
+        // Construct groups in one pass over the pages, sorted by name; nested and derived keys
+        // such as `series.name` and `created_at.year` make groups too
+        let group_names = pages.iter().map(|p| p.metadata.group_names()).collect::<Vec<_>>();
+        let mut group_pages = BTreeMap::<&str, Vec<PageReference>>::new();
+        for (i, names) in group_names.iter().enumerate() {
+            for name in names {
+                group_pages.entry(name).or_default().push(PageReference(i));
+            }
+        }
+        let groups = group_pages.into_iter()
+            .map(|(key, refs)| Group::new(key, refs))
+            .collect::<Vec<_>>();
+        let groups_by_name = groups.iter().enumerate()
+            .map(|(i, g)| (g.name().to_owned(), GroupReference(i)))
+            .collect::<HashMap<_, _>>();
+        let pages_by_url = pages.iter().enumerate()
+            .map(|(i, p)| (p.display_url(), PageReference(i)))
+            .collect::<HashMap<_, _>>();
+        let pages_by_path = pages.iter().enumerate()
+            .map(|(i, p)| (p.path().to_owned(), PageReference(i)))
+            .collect();
+
+        // Resolve page references in metadata; the referred pages get backreferences
+        let mut pages = pages;
+        for i in 0..pages.len() {
+            for (key, url) in references::find(&pages[i].metadata) {
+                match references::lookup(&url, &pages_by_url) {
+                    Some(target) => {
+                        pages[i].references.push((key.clone(), target));
+                        pages[target.0].refs.entry(key).or_default().push(PageReference(i));
+                    }
+                    None => errors.push(RotuliError::Metadata {
+                        path: dir.join(pages[i].path().strip_prefix("/").expect("paths are absolute")),
+                        location: None,
+                        message: format!("`{}' refers to {}, which is not a page", key, url),
+                    }),
+                }
+            }
+        }
+
+        // Check the metadata against the schema of the source directory, if it has one
+        let mut notes = Vec::new();
+        match Schema::load(&dir) {
+            Ok(Some(schema)) => {
+                for (p, names) in pages.iter().zip(&group_names) {
+                    let path = dir.join(p.path().strip_prefix("/").expect("paths are absolute"));
+                    let groups = || names.iter().map(|name| name.as_str());
+                    for message in schema.check(&p.metadata, groups(), p.template_name()) {
+                        errors.push(RotuliError::Metadata { path: path.clone(), location: None, message });
+                    }
+                    for message in schema.notes(&p.metadata, groups(), p.template_name()) {
+                        notes.push((path.clone(), message));
+                    }
+                }
+                for (r, message) in schema.check_site(&pages, &pages_by_url) {
+                    errors.push(RotuliError::Metadata {
+                        path: dir.join(pages[r.0].path().strip_prefix("/").expect("paths are absolute")),
+                        location: None,
+                        message,
+                    });
+                }
+            }
+            Ok(None) => (),
+            Err(e) => errors.push(e),
+        }
+
+        // Transpose groups from (list of pages by group ref) into (list of groups by page ref),
+        // in the order of the metadata keys
+        for (p, names) in pages.iter_mut().zip(&group_names) {
+            p.groups = names.iter()
+                .map(|name| groups_by_name[name])
+                .collect();
+        }
+
+        // Move pages to site
+        let site = Site {
+            directory: dir,
+            pages,
+            groups,
+            plain_files: src_plain_files,
+            draft_key: options.draft_key.to_owned(),
+            groups_by_name,
+            pages_by_url,
+            pages_by_path,
+            notes,
+        };
+
+        // values that cannot be urls of generated taxonomy pages are left out of them
+        for p in &site.pages {
+            let taxonomy = match p.taxonomy() {
+                Some(taxonomy) => taxonomy,
+                None => continue,
+            };
+            for value in site.taxonomy_values(taxonomy).into_keys().filter(|v| !taxonomy::is_url_segment(v)) {
+                errors.push(RotuliError::Metadata {
+                    path: site.source_path(p),
+                    location: None,
+                    message: format!("`{}' value {:?} cannot be a directory in urls", taxonomy.group, value),
+                });
+            }
+        }
+
+        // the numbered pages of listings would overwrite the pages at the same urls
+        for p in &site.pages {
+            let pagination = match p.pagination() {
+                Some(pagination) => pagination,
+                None => continue,
+            };
+            let url = p.display_url();
+            for number in 2..=site.page_count(pagination) {
+                let page_url = pagination::page_url(&url, number);
+                if let Some(other) = site.page_by_url(&page_url) {
+                    errors.push(RotuliError::DuplicateUrl {
+                        paths: vec![site.source_path(p), site.source_path(site.page(other))],
+                        url: page_url,
+                    });
+                }
+            }
+        }
+
+        (site, errors)
+    }
+
+    /// Things about the pages that are worth telling but are not errors, such as recommended
+    /// metadata keys that are missing, with the source file of each.
+    pub fn notes(&self) -> &[(PathBuf, String)] {
+        &self.notes
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pages.is_empty()
+    }
+
+    pub fn enumerate_pages(&self) -> impl Iterator<Item = (PageReference, &Page)> {
+        self.pages.iter().enumerate().map(|(i, p)| (PageReference(i), p))
+    }
+
+    pub fn enumerate_groups(&self) -> impl Iterator<Item = (GroupReference, &Group)> {
+        self.groups.iter().enumerate().map(|(i, g)| (GroupReference(i), g))
+    }
+
+    pub fn page(&self, page: PageReference) -> &Page {
+        &self.pages[page.0]
+    }
+
+    pub fn group(&self, group: GroupReference) -> &Group {
+        &self.groups[group.0]
+    }
+
+    /// Files that are not markup and get copied to the output as-is.
+    pub fn plain_files(&self) -> &[PathBuf] {
+        &self.plain_files
+    }
+
+    /// Where the page came from on disk, for diagnostics.
+    pub fn source_path(&self, page: &Page) -> PathBuf {
+        self.directory.join(page.path().strip_prefix("/").expect("page paths are absolute"))
+    }
+
+    /// The groups of a page in the order of its metadata keys.
+    pub fn groups_for(&self, page: PageReference) -> &[GroupReference] {
+        self.pages[page.0].groups()
+    }
+
+    pub fn get_group(&self, name: &str) -> Option<GroupReference> {
+        self.groups_by_name.get(name).copied()
+    }
+
+    pub fn group_pages(&self, group: GroupReference) -> impl Iterator<Item = &Page> {
+        self.groups[group.0].pages().iter().map(move |p| &self.pages[p.0])
+    }
+
+    // how many numbered pages a listing has; at least one even if it is empty
+    fn page_count(&self, pagination: &Pagination) -> usize {
+        self.paginated_pages(pagination).len().div_ceil(pagination.per_page).max(1)
+    }
+
+    /// The pages that a paginated page lists, oldest first, before they are split to pages.
+    pub fn paginated_pages(&self, pagination: &Pagination) -> Vec<PageReference> {
+        let mut pages = match self.get_group(&pagination.group) {
+            Some(group) => self.groups[group.0].pages().iter().copied()
+                .filter(|r| pagination.includes(&self.pages[r.0].metadata))
+                .collect::<Vec<_>>(),
+            None => vec![],
+        };
+        // stable, so the group order is kept for equal keys
+        pages.sort_by(|a, b| pagination.compare(&self.pages[a.0].metadata, &self.pages[b.0].metadata));
+        pages
+    }
+
+    // every value of the group of a taxonomy with its pages, in the order of the values
+    fn taxonomy_values(&self, taxonomy: &Taxonomy) -> BTreeMap<String, Vec<PageReference>> {
+        let mut values = BTreeMap::<String, Vec<PageReference>>::new();
+        if let Some(group) = self.get_group(&taxonomy.group) {
+            for &r in self.groups[group.0].pages() {
+                for value in taxonomy.values(&self.pages[r.0].metadata) {
+                    values.entry(value).or_default().push(r);
+                }
+            }
+        }
+        values
+    }
+
+    /// The values that a taxonomy page is rendered for, in order, each with its pages in the
+    /// group order. Values that have a source page of their own at their url are left out.
+    pub fn taxonomy_terms(&self, page: PageReference) -> Vec<(String, Vec<PageReference>)> {
+        let p = &self.pages[page.0];
+        let taxonomy = match p.taxonomy() {
+            Some(taxonomy) => taxonomy,
+            None => return vec![],
+        };
+        let url = p.display_url();
+        self.taxonomy_values(taxonomy).into_iter()
+            .filter(|(value, _)| taxonomy::is_url_segment(value)
+                && self.page_by_url(&taxonomy::term_url(&url, value)).is_none())
+            .collect()
+    }
+
+    // the feed items of a feed page as xml, newest first
+    fn render_feed(&self, feed: &Feed, page: &Page, base_url: &str) -> Result<String> {
+        let mut items = Vec::new();
+        if let Some(group) = self.get_group(&feed.group) {
+            for p in self.group_pages(group).filter(|p| feed.includes(&p.metadata)) {
+                let date = match p.metadata.lookup(&feed.date) {
+                    Some(date) => date,
+                    None => continue,
+                };
+                let date = date.as_str().and_then(feed::parse_date).ok_or_else(|| RotuliError::Metadata {
+                    path: self.source_path(p),
+                    location: None,
+                    message: format!("`{}' is not a date, needed by the feed {}", feed.date, page.display_url()),
+                })?;
+                items.push((p, date));
+            }
+        }
+        // stable, so the group order is kept for equal dates
+        items.sort_by_key(|&(_, date)| std::cmp::Reverse(date));
+        items.truncate(feed.limit.unwrap_or(items.len()));
+        Ok(feed::render(feed, page, &items, base_url))
+    }
+
+    /// Find a page by its url as in `Page::display_url`, e.g., "/blog/hello/".
+    pub fn page_by_url(&self, url: &str) -> Option<PageReference> {
+        self.pages_by_url.get(url).copied()
+    }
+
+    /// Find a page by its source path relative to the source directory as in `Page::path`, e.g.,
+    /// "/blog/hello.rst".
+    pub fn page_by_path<P: AsRef<Path>>(&self, path: P) -> Option<PageReference> {
+        self.pages_by_path.get(path.as_ref()).copied()
+    }
+
+    /// Render the pages through their templates into the output directory of the build,
+    /// stopping at the first error. Pages that are up to date since the previous build are
+    /// skipped.
+    pub fn render(&self, tera: &Tera, base_url: &str, state: &mut BuildState) -> Result<()> {
+        let mut done = Vec::new();
+        let result = self.render_each(tera, base_url, state, |_, output_path, deps, rendered| {
+            if let Some(rendered) = rendered {
+                write_output(&state.output_dir().join(&output_path), rendered?.as_bytes())?;
+            }
+            done.push((output_path, deps));
+            Ok(())
+        });
+        for (output, deps) in done {
+            state.record(output, deps);
+        }
+        result
+    }
+
+    /// Render every page that renders fine and is not up to date to memory, and return the
+    /// errors of the others.
+    pub fn render_keep_going(&self, tera: &Tera, base_url: &str, state: &mut BuildState)
+    -> (Vec<RenderedPage>, Vec<RotuliError>) {
+        let mut rendered_pages = Vec::new();
+        let mut up_to_date = Vec::new();
+        let mut errors = Vec::new();
+        let result = self.render_each(tera, base_url, state, |_, output_path, dependencies, rendered| {
+            match rendered {
+                Some(Ok(text)) => rendered_pages.push(RenderedPage { output_path, text, dependencies }),
+                Some(Err(e)) => errors.push(e),
+                None => up_to_date.push((output_path, dependencies)),
+            }
+            Ok(())
+        });
+        if let Err(e) = result {
+            errors.push(e);
+        }
+        for (output, deps) in up_to_date {
+            state.record(output, deps);
+        }
+        (rendered_pages, errors)
+    }
+
+    // the site context is built only once for all pages; each render result is given to the
+    // callback with its output path, and errors of the callback stop the rendering. Paginated
+    // and taxonomy pages have many outputs. Outputs that are up to date in the build state are
+    // given with no result.
+    fn render_each<F>(&self, tera: &Tera, base_url: &str, state: &BuildState, mut f: F) -> Result<()>
+    where F: FnMut(&Page, PathBuf, Dependencies, Option<Result<String>>) -> Result<()> {
+        // a page that is referred to, or that refers to another; not any deeper to avoid cycles
+        #[derive(Debug, Serialize)]
+        struct ReferenceContext<'a> {
+            path: &'a str,
+            url: String,
+            title: &'a str,
+            meta: &'a serde_yaml::Mapping,
+            summary: &'a str,
+        }
+
+        #[derive(Debug, Serialize)]
+        struct PageContext<'a> {
+            path: &'a str,
+            url: String,
+            title: &'a str,
+            /// With page references replaced by the referred pages.
+            meta: Cow<'a, serde_yaml::Mapping>,
+            summary: &'a str,
+            /// The pages that refer to this one, by the metadata path of the reference.
+            refs: BTreeMap<&'a str, Vec<ReferenceContext<'a>>>,
+        }
+
+        #[derive(Debug, Serialize)]
+        struct GroupContext<'a> {
+            name: &'a str,
+            pages: Vec<&'a PageContext<'a>>,
+            // serde_yaml::Value does not know std::cmp::Ord, so let's play with strings for now
+            pages_by_str_value: BTreeMap<Cow<'a, str>, Vec<&'a PageContext<'a>>>,
+            // all values, see value_strings
+            pages_by_value: BTreeMap<String, Vec<&'a PageContext<'a>>>,
+        }
+
+        #[derive(Debug, Serialize)]
+        struct SiteContext<'a> {
+            directory: String,
+            base_url: String,
+            pages: &'a Vec<PageContext<'a>>,
+            pages_by_url: BTreeMap<&'a str, &'a PageContext<'a>>,
+            groups: BTreeMap<&'a str, GroupContext<'a>>,
+        }
+
+        #[derive(Debug, Serialize)]
+        struct PaginatorContext<'a> {
+            items: Vec<&'a PageContext<'a>>,
+            /// From 1, oldest first.
+            number: usize,
+            total: usize,
+            total_items: usize,
+            per_page: usize,
+            /// Of all pages, in the order of their numbers.
+            urls: Vec<String>,
+            prev_url: Option<String>,
+            next_url: Option<String>,
+        }
+
+        #[derive(Debug, Serialize)]
+        struct TermContext<'a> {
+            value: String,
+            url: String,
+            pages: Vec<&'a PageContext<'a>>,
+        }
+
+        // XXX: this is here for now to remind about a possible additional post-load draft flag
+        let page_ok = |p: &&Page| !p.metadata.get_bool_or_false(&self.draft_key);
+        // closure because cloning one filter isn't ergonomic
+        let ok_pages = || self.pages.iter().filter(page_ok);
+
+        fn reference_cx(p: &Page) -> ReferenceContext<'_> {
+            ReferenceContext {
+                path: p.path().to_str().expect("only UTF-8 directories please"),
+                url: p.display_url(),
+                title: p.title(),
+                meta: p.metadata.data(),
+                summary: p.summary_rendered(),
+            }
+        }
+        let resolve = |url: &str| references::lookup(url, &self.pages_by_url).map(|r| {
+            serde_yaml::to_value(reference_cx(&self.pages[r.0])).expect("context serialization failed??")
+        });
+
+        let pages_cx = ok_pages()
+            .map(|p| PageContext {
+                path: p.path().to_str().expect("only UTF-8 directories please"),
+                url: p.display_url(),
+                title: p.title(),
+                meta: if p.references().is_empty() {
+                    Cow::Borrowed(p.metadata.data())
+                } else {
+                    Cow::Owned(p.metadata.data().iter()
+                        .map(|(k, v)| (k.clone(), references::replace(v, &resolve)))
+                        .collect())
+                },
+                summary: p.summary_rendered(),
+                refs: p.refs().iter()
+                    .map(|(key, pages)| (key.as_str(), pages.iter().map(|r| reference_cx(&self.pages[r.0])).collect()))
+                    .collect(),
+            }).collect::<Vec<_>>();
+
+        let pages_by_url_cx = pages_cx.iter().map(|p| (p.url.as_str(), p)).collect();
+
+        // Lots and lots of maps for somewhat more ergonomic usage in templates. From this:
+        //   site.groups["language"].pages | filter(attribute="meta.language", value="en") | ...
+        // to this:
+        //   site.groups["language"].pages_by_str_value["en"] | ...
+        // (experimental)
+        let group_pages_by_str_value = |grp: &Group| -> BTreeMap<Cow<str>, Vec<&PageContext>> {
+            let mut map = BTreeMap::new();
+            for pageref in grp.pages() {
+                let val = match self.pages[pageref.0].metadata.lookup(grp.name()) {
+                    Some(val) => val,
+                    None => continue,
+                };
+                let strval = match val {
+                    Cow::Borrowed(MetadataValue::String(s)) => Cow::Borrowed(s.as_str()),
+                    Cow::Owned(MetadataValue::String(s)) => Cow::Owned(s),
+                    _ => continue,
+                };
+                let entry = map.entry(strval).or_insert_with(Vec::new);
+                entry.push(&pages_cx[pageref.0]);
+            }
+            map
+        };
+        // and the same for any values: each item of a list value and stringified numbers and bools
+        //   site.groups["tags"].pages_by_value["rust"] | ...
+        let group_pages_by_value = |grp: &Group| -> BTreeMap<String, Vec<&PageContext>> {
+            let mut map = BTreeMap::new();
+            for pageref in grp.pages() {
+                let val = match self.pages[pageref.0].metadata.lookup(grp.name()) {
+                    Some(val) => val,
+                    None => continue,
+                };
+                for strval in value_strings(&val) {
+                    map.entry(strval).or_insert_with(Vec::new).push(&pages_cx[pageref.0]);
+                }
+            }
+            map
+        };
+
+        let groups_cx = self.groups.iter().map(|g| (g.name(), GroupContext {
+            name: g.name(),
+            pages: g.pages().iter().map(|pageref| &pages_cx[pageref.0]).collect(),
+            pages_by_str_value: group_pages_by_str_value(g),
+            pages_by_value: group_pages_by_value(g),
+        })).collect();
+
+        let directory = self.directory.canonicalize()
+            .map_err(|e| RotuliError::Io { path: self.directory.clone(), source: e })?;
+        let site_cx = SiteContext {
+            directory: directory.to_str().expect("only UTF-8 directories please").to_owned(),
+            base_url: base_url.to_owned(),
+            pages: &pages_cx,
+            pages_by_url: pages_by_url_cx,
+            groups: groups_cx,
+        };
+
+        // what the pages depend on, to skip the ones that are up to date
+        let template_deps = ok_pages().filter_map(|p| p.template_name()).collect::<BTreeSet<_>>().into_iter()
+            .map(|name| (name, template_dependencies(tera, name)))
+            .collect::<BTreeMap<_, _>>();
+        // The site is serialized just once, and fingerprinted from that
+        let site_value = tera::to_value(&site_cx).expect("context serialization failed??");
+        let site_print = if template_deps.values().any(|d| d.uses_site) {
+            Some(fingerprint(&site_value.to_string()))
+        } else {
+            None
+        };
+        let mut base = tera::Map::new();
+        base.insert("site".to_owned(), site_value);
+        let base_cx = Context::from_value(tera::Value::Object(base)).expect("the context is an object");
+        // Each batch of pages that rayon renders on one thread gets its own copy of it when it
+        // renders its first page, and only replaces the page specific parts for each page
+
+        // each output of each page, with the pagination split to pages and the taxonomy pages
+        // repeated for each value
+        let mut outputs = Vec::new();
+        for (i, p) in ok_pages().enumerate() {
+            let pagination = match p.pagination() {
+                Some(pagination) => pagination,
+                None => {
+                    outputs.push((i, p, p.output_path(), None, None));
+                    let url = p.display_url();
+                    for (value, pages) in self.taxonomy_terms(PageReference(i)) {
+                        let output_path = taxonomy::term_output_path(p.output_path(), &value);
+                        let term = TermContext {
+                            url: taxonomy::term_url(&url, &value),
+                            value,
+                            pages: pages.iter().map(|r| &pages_cx[r.0]).collect(),
+                        };
+                        outputs.push((i, p, output_path, None, Some(term)));
+                    }
+                    continue;
+                }
+            };
+            let items = self.paginated_pages(pagination).into_iter()
+                .map(|r| &pages_cx[r.0])
+                .collect::<Vec<_>>();
+            let total = self.page_count(pagination);
+            let url = p.display_url();
+            let urls = (1..=total).map(|n| pagination::page_url(&url, n)).collect::<Vec<_>>();
+            // a page written at the url of a numbered page wins, as with taxonomies
+            for number in (1..=total).filter(|&n| n == 1 || self.page_by_url(&urls[n - 1]).is_none()) {
+                let paginator = PaginatorContext {
+                    items: items.iter().skip((number - 1) * pagination.per_page)
+                        .take(pagination.per_page).copied().collect(),
+                    number,
+                    total,
+                    total_items: items.len(),
+                    per_page: pagination.per_page,
+                    urls: urls.clone(),
+                    prev_url: if number > 1 { Some(urls[number - 2].clone()) } else { None },
+                    next_url: urls.get(number).cloned(),
+                };
+                outputs.push((i, p, pagination::page_output_path(p.output_path(), number), Some(paginator), None));
+            }
+        }
+
+        let results = outputs.par_iter().map_init(|| None, |thread_cx, (i, p, output_path, paginator, term)| {
+            let page_cx = &site_cx.pages[*i];
+            let template_name = match (p.feed(), p.template_name()) {
+                (Some(feed), _) => {
+                    // cheap enough to render just to see if it changed
+                    let xml = self.render_feed(feed, p, base_url);
+                    let deps = Dependencies::Page {
+                        source: self.source_path(p),
+                        page: fingerprint(&xml.as_ref().ok()),
+                        templates: BTreeMap::new(),
+                        site: None,
+                    };
+                    if xml.is_ok() && state.is_up_to_date(output_path, &deps) {
+                        return (deps, None);
+                    }
+                    return (deps, Some(xml));
+                }
+                (None, Some(name)) => name,
+                (None, None) => unreachable!("pages without a template are feeds"),
+            };
+            let tpl_deps = &template_deps[template_name];
+            let deps = Dependencies::Page {
+                source: self.source_path(p),
+                page: fingerprint(&(yaml_string(page_cx), yaml_string(paginator), yaml_string(term),
+                    p.content_rendered(), template_name)),
+                templates: tpl_deps.templates.clone(),
+                site: site_print.clone().filter(|_| tpl_deps.uses_site),
+            };
+            if state.is_up_to_date(output_path, &deps) {
+                return (deps, None);
+            }
+
+            let cx = thread_cx.get_or_insert_with(|| base_cx.clone());
+            cx.insert("page", page_cx);
+            cx.insert("content", p.content_rendered());
+            match paginator {
+                Some(paginator) => cx.insert("paginator", paginator),
+                None => { cx.remove("paginator"); }
+            }
+            match term {
+                Some(term) => cx.insert("term", term),
+                None => { cx.remove("term"); }
+            }
+
+            let tpl_rendered = tera.render(template_name, cx).map_err(|e| {
+                RotuliError::Template { path: self.source_path(p), message: tera_error_message(&e) }
+            });
+            (deps, Some(tpl_rendered))
+        }).collect::<Vec<_>>();
+
+        // in the original order to keep the output deterministic
+        for ((_, p, output_path, _, _), (deps, rendered)) in outputs.into_iter().zip(results) {
+            if rendered.is_some() {
+                println!("render {:?} to {:?} using {}", p.path(), output_path,
+                    p.template_name().unwrap_or("the built-in feed"));
+            }
+            f(p, output_path, deps, rendered)?;
+        }
+
+        Ok(())
+    }
+
+    /// Copy the plain files to the output directory of the build, except those that have not
+    /// changed since the previous build.
+    pub fn copy_plain_files(&self, state: &mut BuildState) -> Result<()> {
+        self.link_plain_files(LinkMode::Copy, state)
+    }
+
+    /// Put the plain files in the output directory with the given mode instead of copying them.
+    /// Files that are already there as they would be made are left alone, even without an
+    /// earlier build state.
+    pub fn link_plain_files(&self, mode: LinkMode, state: &mut BuildState) -> Result<()> {
+        for x in &self.plain_files {
+            let relative_outpath = x.strip_prefix(&self.directory).expect("glob betrayed us");
+            let deps = Dependencies::plain(x, mode)?;
+            let outfile = state.output_dir().join(relative_outpath);
+            if !state.is_up_to_date(relative_outpath, &deps) && !plain::is_in_place(x, &outfile, mode) {
+                // FIXME: create dirs in a separate pass first
+                create_parent_dir(&outfile)?;
+                let used = plain::place(x, &outfile, mode)
+                    .map_err(|e| RotuliError::Io { path: outfile.clone(), source: e })?;
+                if used == mode {
+                    println!("{} {:?} to {:?}", used, x, outfile);
+                } else {
+                    println!("copy {:?} to {:?} because it cannot be a {}", x, outfile, mode);
+                }
+            }
+            state.record(relative_outpath.to_owned(), deps);
+        }
+
+        Ok(())
+    }
+
+    // the page's own date from metadata if it has one, or the time its source was modified
+    fn lastmod(&self, sitemap: &Sitemap, page: &Page) -> Result<Option<Timestamp>> {
+        match sitemap.date_keys.iter().find_map(|k| page.metadata.lookup(k).map(|v| (k, v))) {
+            Some((key, value)) => value.as_str().and_then(feed::parse_date).map(Some).ok_or_else(|| {
+                RotuliError::Metadata {
+                    path: self.source_path(page),
+                    location: None,
+                    message: format!("`{}' is not a date, needed by the sitemap", key),
+                }
+            }),
+            None => Ok(std::fs::metadata(self.source_path(page)).and_then(|m| m.modified()).ok()
+                .map(Timestamp::from)),
+        }
+    }
+
+    /// Write sitemap.xml of the pages and the generated taxonomy pages to the output directory of
+    /// the build, unless it is up to date. Feeds and pages with `sitemap: false` are left out.
+    pub fn write_sitemap(&self, sitemap: &Sitemap, base_url: &str, state: &mut BuildState) -> Result<()> {
+        let base_url = base_url.trim_end_matches('/');
+        let url_path = format!("/{}", sitemap::OUTPUT);
+        let clashing = self.page_by_url(&url_path).map(|p| self.source_path(self.page(p)))
+            .or_else(|| self.plain_files.iter().find(|f| f.strip_prefix(&self.directory)
+                .is_ok_and(|f| f == Path::new(sitemap::OUTPUT))).cloned());
+        if let Some(path) = clashing {
+            return Err(RotuliError::Metadata {
+                path,
+                location: None,
+                message: format!("this would be overwritten by the sitemap at {}", url_path),
+            });
+        }
+
+        let listed = |p: &Page| !p.metadata.get_bool_or_false(&self.draft_key) && p.feed().is_none()
+            && p.metadata.get(MAGIC_META_SITEMAP).is_none_or(|v| v.as_bool() == Some(true));
+        let language = |p: &Page| p.metadata.lookup(&sitemap.language_key)
+            .and_then(|v| v.as_str().map(|s| s.to_owned()));
+
+        // the languages and urls of each translation key
+        let mut translations = HashMap::<String, Vec<(String, String)>>::new();
+        for p in self.pages.iter().filter(|p| listed(p)) {
+            let (key, language) = match (p.metadata.lookup(&sitemap.translation_key), language(p)) {
+                (Some(key), Some(language)) => (key, language),
+                _ => continue,
+            };
+            for value in value_strings(&key) {
+                let url = format!("{}{}", base_url, p.display_url());
+                translations.entry(value).or_default().push((language.clone(), url));
+            }
+        }
+
+        let mut entries = Vec::new();
+        for (i, p) in self.pages.iter().enumerate().filter(|(_, p)| listed(p)) {
+            let lastmod = self.lastmod(sitemap, p)?;
+            let alternates = p.metadata.lookup(&sitemap.translation_key)
+                .filter(|_| language(p).is_some())
+                .map(|key| value_strings(&key).iter()
+                    .filter_map(|v| translations.get(v))
+                    .flatten()
+                    .map(|(language, url)| (language.as_str(), url.clone()))
+                    .collect::<Vec<_>>())
+                .filter(|alternates| alternates.len() > 1)
+                .unwrap_or_default();
+            let url = p.display_url();
+            entries.push(sitemap::Entry { url: format!("{}{}", base_url, url), lastmod, alternates });
+            for (value, _) in self.taxonomy_terms(PageReference(i)) {
+                let url = format!("{}{}", base_url, taxonomy::term_url(&url, &value));
+                entries.push(sitemap::Entry { url, lastmod, alternates: vec![] });
+            }
+        }
+
+        let xml = sitemap::render(&entries);
+        let output = PathBuf::from(sitemap::OUTPUT);
+        let deps = Dependencies::Page {
+            source: self.directory.clone(),
+            page: fingerprint(&xml),
+            templates: BTreeMap::new(),
+            site: None,
+        };
+        if !state.is_up_to_date(&output, &deps) {
+            let outfile = state.output_dir().join(&output);
+            println!("write sitemap to {:?}", outfile);
+            write_output(&outfile, xml.as_bytes())?;
+        }
+        state.record(output, deps);
+        Ok(())
+    }
+}
@@ -0,0 +1,78 @@
+use std::fmt::Write;
+
+use crate::feed::{escape, rfc3339, Timestamp};
+
+/// Where the sitemap is written, relative to the output directory.
+pub(crate) const OUTPUT: &str = "sitemap.xml";
+
+/// Which metadata keys a sitemap is made from; the defaults are those of the sample site.
+///
+/// Pages that share a value of the translation key are alternates of each other in the
+/// languages given by the language key. The last modification date is the first of the date keys
+/// that a page has, or the modification time of its source file.
+#[derive(Debug, Clone)]
+pub struct Sitemap {
+    pub(crate) translation_key: String,
+    pub(crate) language_key: String,
+    pub(crate) date_keys: Vec<String>,
+}
+
+impl Default for Sitemap {
+    fn default() -> Self {
+        Sitemap {
+            translation_key: "translation_key".to_owned(),
+            language_key: "language".to_owned(),
+            date_keys: vec!["updated_at".to_owned(), "created_at".to_owned()],
+        }
+    }
+}
+
+impl Sitemap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn translation_key(mut self, key: &str) -> Self {
+        self.translation_key = key.to_owned();
+        self
+    }
+
+    pub fn language_key(mut self, key: &str) -> Self {
+        self.language_key = key.to_owned();
+        self
+    }
+
+    /// In order of preference, e.g., the date of the latest edit first.
+    pub fn date_keys(mut self, keys: &[&str]) -> Self {
+        self.date_keys = keys.iter().map(|&k| k.to_owned()).collect();
+        self
+    }
+}
+
+/// One url of the sitemap, absolute.
+pub(crate) struct Entry<'a> {
+    pub url: String,
+    pub lastmod: Option<Timestamp>,
+    /// (language, absolute url), including this one.
+    pub alternates: Vec<(&'a str, String)>,
+}
+
+pub(crate) fn render(entries: &[Entry]) -> String {
+    // writing to a string does not fail
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    xml.push_str("<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\" \
+        xmlns:xhtml=\"http://www.w3.org/1999/xhtml\">\n");
+    for entry in entries {
+        let _ = writeln!(xml, "<url>\n<loc>{}</loc>", escape(&entry.url));
+        if let Some(lastmod) = entry.lastmod {
+            let _ = writeln!(xml, "<lastmod>{}</lastmod>", rfc3339(lastmod));
+        }
+        for (language, url) in &entry.alternates {
+            let _ = writeln!(xml, "<xhtml:link rel=\"alternate\" hreflang=\"{}\" href=\"{}\"/>",
+                escape(language), escape(url));
+        }
+        xml.push_str("</url>\n");
+    }
+    xml.push_str("</urlset>\n");
+    xml
+}
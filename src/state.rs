@@ -0,0 +1,195 @@
+use std::collections::BTreeMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Result, RotuliError};
+use crate::plain::LinkMode;
+
+const STATE_FILE: &str = ".rotuli-state";
+
+// Not guaranteed to be stable between Rust versions, but a changed hash only means a rebuild.
+pub(crate) fn fingerprint<T: Hash + ?Sized>(data: &T) -> String {
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// What an output file was made from, as fingerprints where possible.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) enum Dependencies {
+    Page {
+        source: PathBuf,
+        /// The page context and the rendered content.
+        page: String,
+        /// Each template in the extends/import/include chain by name.
+        templates: BTreeMap<String, String>,
+        /// The whole site context, only if some of the templates refer to it.
+        site: Option<String>,
+    },
+    Plain {
+        source: PathBuf,
+        size: u64,
+        modified: Option<SystemTime>,
+        /// How the file was put in place, so that switching the mode redoes it.
+        #[serde(default)]
+        mode: LinkMode,
+    },
+}
+
+impl Dependencies {
+    pub(crate) fn plain(source: &Path, mode: LinkMode) -> Result<Self> {
+        let meta = std::fs::metadata(source)
+            .map_err(|e| RotuliError::Io { path: source.to_owned(), source: e })?;
+        Ok(Dependencies::Plain {
+            source: source.to_owned(),
+            size: meta.len(),
+            modified: meta.modified().ok(),
+            mode,
+        })
+    }
+
+    pub(crate) fn source(&self) -> &Path {
+        match self {
+            Dependencies::Page { source, .. } => source,
+            Dependencies::Plain { source, .. } => source,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct StateFile {
+    version: String,
+    /// Set while a build is going on, so that one that fails halfway is not trusted later.
+    #[serde(default)]
+    incomplete: bool,
+    outputs: BTreeMap<PathBuf, Dependencies>,
+}
+
+/// A record of what has been built into an output directory, for incremental builds.
+///
+/// Each output file is recorded together with what it was made from: the source file, the
+/// templates, and the site data if the templates use it. A later build into the same directory
+/// skips the files whose inputs have not changed, and removes the ones that it does not produce
+/// anymore.
+#[derive(Debug)]
+pub struct BuildState {
+    output_dir: PathBuf,
+    previous: BTreeMap<PathBuf, Dependencies>,
+    /// Whether the previous outputs are known to be what was recorded; if not, everything is
+    /// built again, but the stale files still get removed.
+    trusted: bool,
+    current: BTreeMap<PathBuf, Dependencies>,
+}
+
+impl BuildState {
+    /// A state with no earlier build, so everything gets built.
+    pub fn new<P: AsRef<Path>>(output_dir: P) -> Self {
+        BuildState {
+            output_dir: output_dir.as_ref().to_owned(),
+            previous: BTreeMap::new(),
+            trusted: true,
+            current: BTreeMap::new(),
+        }
+    }
+
+    /// Whether rotuli has built something into this directory before, even if that failed.
+    pub fn exists<P: AsRef<Path>>(output_dir: P) -> bool {
+        output_dir.as_ref().join(STATE_FILE).is_file()
+    }
+
+    /// Read the state of the previous build from the output directory.
+    ///
+    /// The state file is marked incomplete until `save` writes it again, so that a build that
+    /// fails halfway is not trusted the next time; the files of such a build are all built again.
+    /// A missing file or one from another version of rotuli gives the same as `new`.
+    pub fn load<P: AsRef<Path>>(output_dir: P) -> Result<Self> {
+        let mut state = BuildState::new(output_dir);
+        let path = state.output_dir.join(STATE_FILE);
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => Some(text),
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => return Err(RotuliError::Io { path, source: e }),
+        };
+        match text.as_deref().map(serde_yaml::from_str::<StateFile>) {
+            Some(Ok(file)) if file.version == env!("CARGO_PKG_VERSION") => {
+                if file.incomplete {
+                    println!("note: the previous build into {:?} did not finish, building everything",
+                        state.output_dir);
+                }
+                state.previous = file.outputs;
+                state.trusted = !file.incomplete;
+            }
+            Some(_) => println!("note: ignoring the unknown build state in {:?}", path),
+            None => (),
+        }
+        state.write(true)?;
+        Ok(state)
+    }
+
+    pub fn output_dir(&self) -> &Path {
+        &self.output_dir
+    }
+
+    // the output is there and it was made from the same things
+    pub(crate) fn is_up_to_date(&self, output: &Path, deps: &Dependencies) -> bool {
+        self.trusted && self.previous.get(output) == Some(deps) && self.output_dir.join(output).is_file()
+    }
+
+    pub(crate) fn record(&mut self, output: PathBuf, deps: Dependencies) {
+        self.current.insert(output, deps);
+    }
+
+    // what has been built so far, relative to the output directory, with the source of each
+    pub(crate) fn outputs(&self) -> impl Iterator<Item = (&Path, &Path)> {
+        self.current.iter().map(|(output, deps)| (output.as_path(), deps.source()))
+    }
+
+    /// Keep the plain files of the previous build as they are, for a build that does not copy
+    /// them at all.
+    pub fn keep_plain_files(&mut self) {
+        for (output, deps) in &self.previous {
+            if let Dependencies::Plain { .. } = deps {
+                self.current.entry(output.clone()).or_insert_with(|| deps.clone());
+            }
+        }
+    }
+
+    /// Remove the files of the previous build that were not built this time, and write the state
+    /// file for the next build.
+    pub fn save(&self) -> Result<()> {
+        for stale in self.previous.keys().filter(|p| !self.current.contains_key(*p)) {
+            let path = self.output_dir.join(stale);
+            println!("remove {:?}", path);
+            match std::fs::remove_file(&path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(RotuliError::Io { path, source: e }),
+                _ => (),
+            }
+        }
+
+        self.write(false)
+    }
+
+    // what has been built; the previous outputs too while incomplete, so that the next build
+    // still knows to remove them
+    fn write(&self, incomplete: bool) -> Result<()> {
+        let mut outputs = self.current.clone();
+        if incomplete {
+            outputs.extend(self.previous.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+        let file = StateFile {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            incomplete,
+            outputs,
+        };
+        let text = serde_yaml::to_string(&file).expect("state serialization failed??");
+        std::fs::create_dir_all(&self.output_dir)
+            .map_err(|e| RotuliError::Io { path: self.output_dir.clone(), source: e })?;
+        let path = self.output_dir.join(STATE_FILE);
+        std::fs::write(&path, text).map_err(|e| RotuliError::Io { path, source: e })
+    }
+}
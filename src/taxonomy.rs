@@ -0,0 +1,70 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+use crate::metadata::{value_strings, Metadata, MAGIC_META_PAGINATE, MAGIC_META_TAXONOMY, MAGIC_META_URL_AS_IS};
+
+/// Renders a page once more for each value of a group, as in
+///
+/// ```yaml
+/// taxonomy: { group: category, filter: { language: en } }
+/// ```
+///
+/// A page at `/category/` gets an output at `/category/<value>/` for each value, with the value
+/// and its pages in the `term` of the template context; the page itself is rendered as usual,
+/// without a `term`. A source page at the url of a value is rendered instead of the generated
+/// one, e.g., to say something more about that value.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Taxonomy {
+    /// The values of this group, see `value_strings`.
+    pub group: String,
+    /// Only the pages that have these metadata values count, e.g., those of one language.
+    #[serde(default)]
+    pub filter: BTreeMap<String, String>,
+}
+
+impl Taxonomy {
+    pub(crate) fn from_metadata(metadata: &Metadata) -> Result<Option<Self>, String> {
+        let value = match metadata.get(MAGIC_META_TAXONOMY) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let taxonomy = serde_yaml::from_value::<Taxonomy>(value.clone())
+            .map_err(|e| format!("bad `{}': {}", MAGIC_META_TAXONOMY, e))?;
+        if metadata.contains_key(MAGIC_META_PAGINATE) {
+            return Err(format!("`{}' cannot be used with `{}'", MAGIC_META_TAXONOMY, MAGIC_META_PAGINATE));
+        }
+        if metadata.get_bool_or_false(MAGIC_META_URL_AS_IS) {
+            return Err(format!("`{}' cannot be used with `{}', the values need directories",
+                MAGIC_META_TAXONOMY, MAGIC_META_URL_AS_IS));
+        }
+        Ok(Some(taxonomy))
+    }
+
+    /// The values of the group that a page with this metadata is listed under, if it passes the
+    /// filter.
+    pub fn values(&self, metadata: &Metadata) -> Vec<String> {
+        match metadata.lookup(&self.group) {
+            Some(value) if metadata.matches(&self.filter) => value_strings(&value),
+            _ => vec![],
+        }
+    }
+}
+
+/// Whether a value can be used as a directory name in urls as-is.
+pub(crate) fn is_url_segment(value: &str) -> bool {
+    !value.is_empty() && value != "." && value != ".." && !value.contains(['/', '\\'])
+}
+
+/// The url of the output of `value` of a taxonomy page at `url`, which ends in a slash.
+pub(crate) fn term_url(url: &str, value: &str) -> String {
+    format!("{}{}/", url, value)
+}
+
+/// Like `term_url`, but for the output file relative to the output directory.
+pub(crate) fn term_output_path(output_path: PathBuf, value: &str) -> PathBuf {
+    let dir = output_path.parent().expect("taxonomy pages are in directories");
+    dir.join(value).join("index.html")
+}
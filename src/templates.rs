@@ -0,0 +1,150 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use glob::{glob_with, MatchOptions};
+use regex::Regex;
+use tera::Tera;
+use tera::ast::Node;
+
+use crate::error::{Result, RotuliError};
+use crate::state::fingerprint;
+
+// the interesting part of a Tera error is usually at the end of the chain
+pub(crate) fn tera_error_message(e: &tera::Error) -> String {
+    let mut message = e.to_string();
+    let mut e: &dyn std::error::Error = e;
+    while let Some(x) = e.source() {
+        message += &format!("\n  {}", x);
+        e = x;
+    }
+    message
+}
+
+// all files under the directory with their template names, i.e., the relative paths with slashes
+fn discover_templates(directory: &Path) -> Result<Vec<(PathBuf, String)>> {
+    if let Err(e) = std::fs::read_dir(directory) {
+        return Err(RotuliError::Io { path: directory.to_owned(), source: e });
+    }
+    let pathname = directory.to_str().expect("only UTF-8 directories please").to_owned();
+    let opts = MatchOptions {
+        require_literal_leading_dot: true,
+        ..Default::default()
+    };
+    let paths = glob_with(&(pathname + "/**/*"), opts).expect("invalid search pattern");
+    Ok(paths.filter_map(|x| x.ok()).filter(|x| x.is_file()).map(|path| {
+        let name = path.strip_prefix(directory).expect("glob betrayed us")
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        (path, name)
+    }).collect())
+}
+
+/// Load all templates from the given directories and register the rotuli filters.
+///
+/// Every file is a template, not just `.html` ones, so that e.g. `feed.xml` and `robots.txt` can
+/// be templates too; the name of a template is its path relative to its directory. If the same
+/// name is found in several directories, the last one wins, so a site can override some
+/// templates of a shared theme. As usual in Tera, `.html` and `.xml` templates are autoescaped.
+pub fn load_templates<P: AsRef<Path>>(directories: &[P]) -> Result<Tera> {
+    let mut sources = Vec::new();
+    for directory in directories {
+        for (path, name) in discover_templates(directory.as_ref())? {
+            let text = std::fs::read_to_string(&path)
+                .map_err(|e| RotuliError::Io { path: path.clone(), source: e })?;
+            // parse each one alone first to blame the right file for syntax errors
+            tera::Template::new(&name, None, &text)
+                .map_err(|e| RotuliError::Template {
+                    path: path.clone(),
+                    message: format!("syntax error\n{}", tera_error_message(&e)),
+                })?;
+            sources.retain(|(_, n): &(PathBuf, String)| *n != name);
+            sources.push((path, name));
+        }
+    }
+
+    let mut tera = Tera::default();
+    // from the files again so that they know their paths for the build state
+    tera.add_template_files(sources.iter().map(|(path, name)| (path, Some(name))))
+        .map_err(|e| {
+            // inheritance problems and such; no single file to blame, so take the first one
+            let path = directories.first().map(|d| d.as_ref().to_owned()).unwrap_or_default();
+            RotuliError::Template { path, message: tera_error_message(&e) }
+        })?;
+    crate::filters::register(&mut tera);
+    Ok(tera)
+}
+
+// the templates that these nodes include; extends and imports are known by the template already
+fn find_includes(nodes: &[Node], names: &mut Vec<String>) {
+    for node in nodes {
+        match node {
+            Node::Include(_, files, _) => names.extend(files.iter().cloned()),
+            Node::MacroDefinition(_, m, _) => find_includes(&m.body, names),
+            Node::FilterSection(_, f, _) => find_includes(&f.body, names),
+            Node::Block(_, b, _) => find_includes(&b.body, names),
+            Node::Forloop(_, f, _) => {
+                find_includes(&f.body, names);
+                find_includes(f.empty_body.as_deref().unwrap_or(&[]), names);
+            }
+            Node::If(i, _) => {
+                for (_, _, body) in &i.conditions {
+                    find_includes(body, names);
+                }
+                if let Some((_, body)) = &i.otherwise {
+                    find_includes(body, names);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// What rendering with one template depends on.
+pub(crate) struct TemplateDependencies {
+    /// Fingerprints of the template and of everything that it extends, imports or includes.
+    pub templates: BTreeMap<String, String>,
+    /// Whether any of those might read the site context.
+    pub uses_site: bool,
+}
+
+pub(crate) fn template_dependencies(tera: &Tera, name: &str) -> TemplateDependencies {
+    let site_ref = Regex::new(r"\b(site|__tera_context)\b").expect("bad regex");
+    let mut deps = TemplateDependencies { templates: BTreeMap::new(), uses_site: false };
+    let mut queue = vec![name.to_owned()];
+
+    while let Some(name) = queue.pop() {
+        if deps.templates.contains_key(&name) {
+            continue;
+        }
+        // a missing one fails the rendering anyway, or is an ignored optional include
+        let template = match tera.get_template(&name) {
+            Ok(t) => t,
+            Err(_) => continue,
+        };
+        let text = template.path.as_ref().and_then(|p| std::fs::read_to_string(p).ok());
+        let print = match text {
+            Some(text) => {
+                deps.uses_site |= site_ref.is_match(&text);
+                fingerprint(&text)
+            }
+            None => {
+                // added from a string by the caller; the debug output of the parsed template
+                // will have to do, although it is not deterministic with macro arguments
+                deps.uses_site = true;
+                fingerprint(&format!("{:?}", template.ast))
+            }
+        };
+        deps.templates.insert(name, print);
+
+        queue.extend(template.parents.iter().cloned());
+        queue.extend(template.imported_macro_files.iter().map(|(file, _)| file.clone()));
+        find_includes(&template.ast, &mut queue);
+        for m in template.macros.values() {
+            find_includes(&m.body, &mut queue);
+        }
+    }
+
+    deps
+}